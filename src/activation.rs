@@ -0,0 +1,184 @@
+//! D-Bus activation of `DBusActivatable=true` entries through the
+//! `org.freedesktop.Application` interface.
+
+use crate::launcher::AppEntry;
+use gio::prelude::*;
+use std::collections::HashMap;
+use zbus::zvariant::Value;
+use zbus::{Connection, proxy};
+
+#[proxy(interface = "org.freedesktop.Application")]
+trait Application {
+    fn activate(&self, platform_data: HashMap<&str, Value<'_>>) -> zbus::Result<()>;
+
+    fn open(&self, uris: &[&str], platform_data: HashMap<&str, Value<'_>>) -> zbus::Result<()>;
+
+    fn activate_action(
+        &self,
+        action_name: &str,
+        parameter: &[Value<'_>],
+        platform_data: HashMap<&str, Value<'_>>,
+    ) -> zbus::Result<()>;
+}
+
+/// Activates `app` on the session bus. Actions go through `ActivateAction`,
+/// entries with `files` through `Open` and everything else through `Activate`.
+pub async fn activate(
+    app: &AppEntry,
+    files: &[String],
+    activation_token: Option<&str>,
+) -> zbus::Result<()> {
+    let connection = Connection::session().await?;
+    activate_on(&connection, app, files, activation_token).await
+}
+
+/// Activates `app` through `connection`, which lets tests use a private bus.
+pub async fn activate_on(
+    connection: &Connection,
+    app: &AppEntry,
+    files: &[String],
+    activation_token: Option<&str>,
+) -> zbus::Result<()> {
+    let bus_name = app.app_id().trim_end_matches(".desktop");
+    let proxy = ApplicationProxy::builder(connection)
+        .destination(bus_name)?
+        .path(object_path(bus_name))?
+        .build()
+        .await?;
+
+    let mut platform_data = HashMap::new();
+    if let Some(token) = activation_token {
+        platform_data.insert("activation-token", Value::from(token));
+        platform_data.insert("desktop-startup-id", Value::from(token));
+    }
+
+    if let Some(action) = app.action_id() {
+        return proxy.activate_action(action, &[], platform_data).await;
+    }
+    if files.is_empty() {
+        return proxy.activate(platform_data).await;
+    }
+
+    let uris: Vec<String> = files.iter().map(|file| to_uri(file)).collect();
+    let uris: Vec<&str> = uris.iter().map(String::as_str).collect();
+    proxy.open(&uris, platform_data).await
+}
+
+/// Object path an application exports its interface on, derived from its
+/// bus name as the Desktop Entry spec describes.
+fn object_path(bus_name: &str) -> String {
+    format!("/{}", bus_name.replace('.', "/").replace('-', "_"))
+}
+
+fn to_uri(file: &str) -> String {
+    if file.contains("://") {
+        file.to_string()
+    } else {
+        gio::File::for_path(file).uri().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::launcher::AppAction;
+    use crate::testing::PrivateBus;
+    use std::sync::{Arc, Mutex};
+    use zbus::zvariant::OwnedValue;
+
+    /// Records the calls it receives as strings.
+    struct StubApplication(Arc<Mutex<Vec<String>>>);
+
+    impl StubApplication {
+        fn record(&self, call: String, platform_data: &HashMap<String, OwnedValue>) {
+            let token = platform_data
+                .get("activation-token")
+                .and_then(|token| String::try_from(token.clone()).ok());
+            self.0.lock().unwrap().push(format!("{} {:?}", call, token));
+        }
+    }
+
+    #[zbus::interface(name = "org.freedesktop.Application")]
+    impl StubApplication {
+        fn activate(&self, platform_data: HashMap<String, OwnedValue>) {
+            self.record(String::from("Activate"), &platform_data);
+        }
+
+        fn open(&self, uris: Vec<String>, platform_data: HashMap<String, OwnedValue>) {
+            self.record(format!("Open {:?}", uris), &platform_data);
+        }
+
+        fn activate_action(
+            &self,
+            action_name: String,
+            _parameter: Vec<OwnedValue>,
+            platform_data: HashMap<String, OwnedValue>,
+        ) {
+            self.record(format!("ActivateAction {}", action_name), &platform_data);
+        }
+    }
+
+    fn editor() -> AppEntry {
+        AppEntry {
+            id: String::from("org.example.Text-Editor.desktop"),
+            name: String::from("Text Editor"),
+            dbus_activatable: true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn activates_through_the_application_interface() {
+        let bus = PrivateBus::start();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let _service = bus
+            .builder()
+            .name("org.example.Text-Editor")
+            .unwrap()
+            .serve_at("/org/example/Text_Editor", StubApplication(calls.clone()))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let connection = bus.connect().await;
+
+        let app = editor();
+        activate_on(&connection, &app, &[], Some("token-1"))
+            .await
+            .unwrap();
+        let files = [
+            String::from("/tmp/notes.txt"),
+            String::from("https://example.com/"),
+        ];
+        activate_on(&connection, &app, &files, None).await.unwrap();
+        let action = app.action_entry(&AppAction {
+            id: String::from("new-window"),
+            name: String::from("New Window"),
+            exec: String::from("editor --new-window"),
+            icon_name: None,
+        });
+        activate_on(&connection, &action, &[], Some("token-2"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "Activate Some(\"token-1\")",
+                "Open [\"file:///tmp/notes.txt\", \"https://example.com/\"] None",
+                "ActivateAction new-window Some(\"token-2\")",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_application_is_not_on_the_bus() {
+        let bus = PrivateBus::start();
+        let connection = bus.connect().await;
+        assert!(
+            activate_on(&connection, &editor(), &[], None)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,26 +1,131 @@
+use crate::control::{self, Command};
+use crate::provider;
+use crate::search::SearchMode;
 use crate::ui::LauncherWindow;
-use gtk4::prelude::*;
 use gtk4::Application;
+use gtk4::gio::{ApplicationHoldGuard, Cancellable};
+use gtk4::glib;
+use gtk4::prelude::*;
+use log::error;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tokio::sync::mpsc;
+
+/// How the launcher window was asked for on the command line.
+#[derive(Default)]
+pub struct Options {
+    /// Stay resident with the window hidden between uses.
+    pub daemon: bool,
+    /// Query typed in when the window opens.
+    pub query: Option<String>,
+    pub mode: Option<SearchMode>,
+}
 
 pub struct App {
     app: Application,
+    options: Options,
+}
+
+/// State of the primary instance, shared between its signal handlers.
+struct Instance {
+    daemon: bool,
+    window: RefCell<Option<LauncherWindow>>,
+    /// Applied to the window once it is built.
+    initial_commands: RefCell<Vec<Command>>,
+    /// Keeps a daemon running while its window is hidden.
+    hold: RefCell<Option<ApplicationHoldGuard>>,
 }
 
 impl App {
-    pub fn new() -> Self {
+    pub fn new(options: Options) -> Self {
         let app = Application::builder()
             .application_id("hyprlauncher")
             .build();
 
-        Self { app }
+        Self { app, options }
     }
 
-    pub fn run(&self) {
+    pub fn run(self) {
+        if let Err(e) = self.app.register(None::<&Cancellable>) {
+            error!("Failed to register the application: {}", e);
+            return;
+        }
+
+        let Options {
+            daemon,
+            query,
+            mode,
+        } = self.options;
+        if self.app.is_remote() && (query.is_some() || mode.is_some()) {
+            // Activating the running instance would only toggle it, so ask
+            // it for the requested query over its control interface instead.
+            let show = control::show_remote(query.as_deref(), mode);
+            if let Err(e) = glib::MainContext::default().block_on(show) {
+                error!("Failed to reach the running launcher: {}", e);
+            }
+            return;
+        }
+        // A remote toggle leaves the providers to the primary instance.
+        if !self.app.is_remote() {
+            provider::init();
+        }
+
+        let initial_commands = mode
+            .map(Command::SetMode)
+            .into_iter()
+            .chain(query.map(Command::SetQuery))
+            .collect();
+        let instance = Rc::new(Instance {
+            daemon,
+            window: RefCell::new(None),
+            initial_commands: RefCell::new(initial_commands),
+            hold: RefCell::new(None),
+        });
+
+        // Runs in the primary instance, for its own start and for every
+        // later `hyprlauncher` invocation.
         self.app.connect_activate(move |app| {
-            let window = LauncherWindow::new(app);
-            window.present();
+            if let Some(window) = instance.window.borrow().as_ref().filter(|w| w.is_alive()) {
+                window.toggle();
+                return;
+            }
+
+            let window = LauncherWindow::new(app, instance.daemon);
+            for command in instance.initial_commands.take() {
+                window.handle(command);
+            }
+            if instance.daemon {
+                instance.hold.replace(Some(app.hold()));
+            } else {
+                window.present();
+            }
+            if instance.window.replace(Some(window)).is_none() {
+                serve_control(instance.clone());
+            }
         });
 
-        self.app.run();
+        // Options were parsed already, GTK only gets the program name.
+        let program: Vec<String> = std::env::args().take(1).collect();
+        self.app.run_with_args(&program);
     }
 }
+
+/// Exports the D-Bus control interface and applies its commands to the
+/// window of `instance`.
+fn serve_control(instance: Rc<Instance>) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    glib::spawn_future_local(async move {
+        let _connection = match control::serve(tx).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("Failed to export {} on D-Bus: {}", control::BUS_NAME, e);
+                return;
+            }
+        };
+        while let Some(command) = rx.recv().await {
+            if let Some(window) = instance.window.borrow().as_ref().filter(|w| w.is_alive()) {
+                window.handle(command);
+            }
+        }
+    });
+}
@@ -0,0 +1,98 @@
+//! On-disk copy of the application index, so a cold start can show results
+//! before any desktop file or `PATH` directory has been read.
+
+use crate::launcher::AppEntry;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+/// Per-directory modification times the index was built from.
+pub type Signatures = HashMap<PathBuf, Option<SystemTime>>;
+
+#[derive(Serialize, Deserialize)]
+pub struct IndexCache {
+    /// Describes everything besides file contents that shaped the index
+    /// (format version, search directories, locale, ...). A cache built
+    /// under a different fingerprint is ignored.
+    pub fingerprint: String,
+    pub signatures: Signatures,
+    pub apps: HashMap<String, AppEntry>,
+}
+
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("hyprlauncher").join("index.json"))
+}
+
+/// Reads the cached index if it exists and matches `fingerprint`.
+pub fn load(fingerprint: &str) -> Option<IndexCache> {
+    let path = cache_path()?;
+    let contents = fs::read(&path).ok()?;
+    match serde_json::from_slice::<IndexCache>(&contents) {
+        Ok(cache) if cache.fingerprint == fingerprint => Some(cache),
+        Ok(_) => {
+            info!("Ignoring index cache {:?} built for another setup", path);
+            None
+        }
+        Err(e) => {
+            error!("Failed to parse index cache {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Writes `cache` atomically, so a concurrent reader never sees half a file.
+pub fn save(cache: &IndexCache) {
+    let Some(path) = cache_path() else {
+        return;
+    };
+    if let Some(dir) = path.parent()
+        && let Err(e) = fs::create_dir_all(dir)
+    {
+        error!("Failed to create directory {:?}: {}", dir, e);
+        return;
+    }
+
+    let tmp_path = path.with_extension("json.tmp");
+    let result = serde_json::to_vec(cache)
+        .map_err(std::io::Error::from)
+        .and_then(|contents| fs::write(&tmp_path, contents))
+        .and_then(|()| fs::rename(&tmp_path, &path));
+    match result {
+        Ok(()) => info!(
+            "Saved {} entries to index cache {:?}",
+            cache.apps.len(),
+            path
+        ),
+        Err(e) => error!("Failed to write index cache {:?}: {}", path, e),
+    }
+}
+
+/// Computes the signature of every directory the index is built from.
+///
+/// `applications` directories take the newest modification time of any
+/// subdirectory or desktop file inside them, so in-place edits are noticed.
+/// `PATH` directories only use their own time, which changes whenever a
+/// binary is added, removed or replaced.
+pub fn signatures(app_dirs: &[PathBuf], path_dirs: &[PathBuf]) -> Signatures {
+    let app_signatures = app_dirs.iter().map(|dir| {
+        let newest = WalkDir::new(dir)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_dir() || e.path().extension() == Some("desktop".as_ref()))
+            .filter_map(|e| e.metadata().ok()?.modified().ok())
+            .max();
+        (dir.clone(), newest)
+    });
+    let path_signatures = path_dirs.iter().map(|dir| (dir.clone(), modified(dir)));
+
+    app_signatures.chain(path_signatures).collect()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).ok()?.modified().ok()
+}
@@ -0,0 +1,184 @@
+//! Command-line interface, including the commands that run without GTK.
+
+use crate::app::{self, App};
+use crate::launcher;
+use crate::notification;
+use crate::picker;
+use crate::provider::{self, Activation};
+use crate::search::SearchMode;
+use crate::spawn::{self, LaunchOptions};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Keep the launcher resident in the background; running hyprlauncher
+    /// again toggles it.
+    #[arg(short, long)]
+    pub daemon: bool,
+
+    /// Open the launcher with this query already typed in.
+    #[arg(short, long)]
+    pub query: Option<String>,
+
+    /// What to search: all, or one provider such as apps, run or files.
+    #[arg(short, long, global = true, value_name = "MODE")]
+    pub mode: Option<SearchMode>,
+
+    /// Read the configuration from this file instead of config.toml.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Read the stylesheet from this file instead of style.css.
+    #[arg(long, value_name = "PATH")]
+    pub css: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub picker: PickerArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Options of the dmenu and rofi script modes.
+#[derive(Args)]
+pub struct PickerArgs {
+    /// Read entries from stdin and print the chosen one to stdout, like
+    /// dmenu. Exits with 1 when cancelled.
+    #[arg(long)]
+    pub dmenu: bool,
+
+    /// Show the rows printed by a rofi mode script, running it again with
+    /// the chosen one.
+    #[arg(long, value_name = "PATH", conflicts_with = "dmenu")]
+    pub script: Option<PathBuf>,
+
+    /// Text shown in front of the search field.
+    #[arg(short, long)]
+    pub prompt: Option<String>,
+
+    /// Match case-insensitively.
+    #[arg(short = 'i', long = "insensitive")]
+    pub ignore_case: bool,
+
+    /// Print the position of the chosen line in the input instead of the
+    /// line, or -1 for typed text.
+    #[arg(long, requires = "dmenu")]
+    pub index: bool,
+
+    /// Allow choosing several lines, marked with Tab.
+    #[arg(long, requires = "dmenu")]
+    pub multi_select: bool,
+
+    /// Mask the typed text.
+    #[arg(long, requires = "dmenu")]
+    pub password: bool,
+
+    /// Print the typed text instead of the selected line, as Shift+Enter
+    /// always does.
+    #[arg(long, requires = "dmenu")]
+    pub print_query: bool,
+}
+
+impl PickerArgs {
+    pub fn picker_options(&self) -> picker::Options {
+        picker::Options {
+            ignore_case: self.ignore_case,
+            multi_select: self.multi_select,
+            password: self.password,
+            choose_text: self.print_query,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print the ranked results for a query without opening the launcher.
+    Search {
+        /// The query, as it would be typed into the search field.
+        #[arg(default_value = "")]
+        query: String,
+
+        /// Print the results as a JSON array.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Launch an entry by its ID, as listed by `search`.
+    Launch {
+        /// Desktop-file ID, action ID, binary name or file path.
+        id: String,
+
+        /// Run the entry inside a terminal.
+        #[arg(long)]
+        terminal: bool,
+    },
+}
+
+pub async fn search(query: &str, mode: &SearchMode, json: bool) -> ExitCode {
+    if provider::is_unknown(mode) {
+        eprintln!("No search provider named {}", mode);
+        return ExitCode::FAILURE;
+    }
+    launcher::load_current_applications().await;
+    let results = provider::search_ranked(query, mode).await;
+
+    if json {
+        match serde_json::to_string_pretty(&results) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to serialize results: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        for result in &results {
+            println!("{}\t{}\t{}", result.score, result.app.id, result.app.name);
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Activates the entry `id` the way choosing it in the window does.
+pub async fn launch(id: &str, in_terminal: bool) -> ExitCode {
+    launcher::load_current_applications().await;
+    let Some(app) = launcher::find_entry(id).await else {
+        eprintln!("No entry with ID {:?}", id);
+        return ExitCode::FAILURE;
+    };
+
+    match provider::activate(&app).await {
+        Activation::Launch => {}
+        Activation::Query(query) => {
+            // E.g. a folder: open the launcher inside it.
+            App::new(app::Options {
+                daemon: false,
+                query: Some(query),
+                mode: None,
+            })
+            .run();
+            return ExitCode::SUCCESS;
+        }
+        Activation::Close => return ExitCode::SUCCESS,
+    }
+
+    let options = LaunchOptions {
+        in_terminal,
+        // Passed on when this command was itself started with a token, e.g.
+        // from another launcher or a compositor bind.
+        activation_token: std::env::var("XDG_ACTIVATION_TOKEN").ok(),
+        ..Default::default()
+    };
+    if app.counts_launches() {
+        launcher::increment_launch_count(&app).await;
+    }
+    match spawn::launch(&app, &[], &options).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Failed to launch {}: {}", app.id, e);
+            notification::launch_failed(&app, &e).await;
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,16 +1,88 @@
 use dirs::config_dir;
+use log::error;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Set by `--config`: read the configuration from this file instead.
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set by `--css`: read the stylesheet from this file instead.
+static CSS_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes [`Config::load`] read `path` instead of the default `config.toml`.
+pub fn set_config_file(path: PathBuf) {
+    let _ = CONFIG_FILE.set(path);
+}
+
+/// Makes [`Config::load_css`] read `path` instead of the default `style.css`.
+pub fn set_css_file(path: PathBuf) {
+    let _ = CSS_FILE.set(path);
+}
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub width: i32,
     pub height: i32,
     pub font_size: i32,
+    /// Lists entries the Desktop Entry spec says to hide (`NoDisplay`,
+    /// `Hidden`, `OnlyShowIn`, missing `TryExec`, ...). Useful for debugging.
+    pub show_hidden_entries: bool,
+    /// Command used to run `Terminal=true` entries, e.g. `"foot"` or
+    /// `"wezterm start --"`. The program is appended to it; a known terminal
+    /// given without arguments gets the ones it needs, such as `-e`. When
+    /// unset, `$TERMINAL`, kitty, foot, alacritty, wezterm, ghostty, konsole,
+    /// gnome-terminal and xterm are tried in order.
+    pub terminal: Option<String>,
+    pub launch_backend: LaunchBackend,
+    /// Hyprland window rules used by the `hyprland` launch backend, keyed by
+    /// desktop-file ID, e.g. `"firefox.desktop" = "workspace 2 silent"`.
+    /// Separate several rules with `;`.
+    pub app_rules: HashMap<String, String>,
+    /// Variables of the launcher's environment passed on to launched apps.
+    /// Empty passes everything. A trailing `*` matches by prefix, e.g.
+    /// `"XDG_*"`.
+    ///
+    /// Whatever is set for the launcher alone reaches apps too unless
+    /// `env_deny` removes it. The default `env_deny` covers `LD_PRELOAD`,
+    /// which is how gtk4-layer-shell is often loaded, GTK's debug and
+    /// renderer switches, and the variables systemd sets for the service.
+    /// Anything else, e.g. a `GTK_THEME` meant only for the launcher, has to
+    /// be added there, or an allow list used instead.
+    pub env_allow: Vec<String>,
+    /// Variables never passed on to launched apps, checked after
+    /// `env_allow`. Same syntax.
+    pub env_deny: Vec<String>,
+    /// Extra environment variables keyed by desktop-file ID, e.g.
+    /// `[app_env."firefox.desktop"]` with `MOZ_ENABLE_WAYLAND = "1"`.
+    pub app_env: HashMap<String, HashMap<String, String>>,
+    /// Search providers to use, in the order their results are merged:
+    /// `apps`, `run`, `files` and plugin names. Plugins not listed are added
+    /// after them.
+    pub providers: Vec<String>,
+    /// Prefixes that send a query to one provider alone, keyed by provider
+    /// name, e.g. `run = ">"`. An empty prefix turns the default one off.
+    pub provider_prefixes: HashMap<String, String>,
     pub theme: Theme,
 }
 
+/// How launched applications are started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LaunchBackend {
+    /// Spawn the process as a child of the launcher.
+    #[default]
+    Exec,
+    /// Spawn the process, then move it into its own systemd user scope.
+    Systemd,
+    /// Ask Hyprland to start the process with `dispatch exec`, applying
+    /// `app_rules`.
+    Hyprland,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Theme {
     pub background_color: String,
@@ -24,6 +96,37 @@ impl Default for Config {
             width: 600,
             height: 600,
             font_size: 14,
+            show_hidden_entries: false,
+            terminal: None,
+            launch_backend: LaunchBackend::default(),
+            app_rules: HashMap::new(),
+            env_allow: Vec::new(),
+            // Debug switches and service bookkeeping meant for the launcher
+            // itself, e.g. when it runs as a systemd service.
+            env_deny: [
+                "RUST_LOG",
+                "RUST_BACKTRACE",
+                "LD_PRELOAD",
+                "GDK_DEBUG",
+                "GDK_DISABLE",
+                "GDK_GL_DISABLE",
+                "GDK_VULKAN_DISABLE",
+                "GTK_DEBUG",
+                "GSK_DEBUG",
+                "GSK_RENDERER",
+                "G_DEBUG",
+                "G_MESSAGES_DEBUG",
+                "INVOCATION_ID",
+                "JOURNAL_STREAM",
+                "MANAGERPID",
+                "NOTIFY_SOCKET",
+                "LISTEN_*",
+            ]
+            .map(String::from)
+            .to_vec(),
+            app_env: HashMap::new(),
+            providers: ["apps", "run", "files"].map(String::from).to_vec(),
+            provider_prefixes: HashMap::new(),
             theme: Theme {
                 background_color: String::from("#0f0f0f"),
                 text_color: String::from("#eceff4"),
@@ -34,6 +137,11 @@ impl Default for Config {
 }
 
 impl Config {
+    /// Folder of the plugin executables.
+    pub fn plugins_dir() -> PathBuf {
+        Self::ensure_config_dir().join("plugins")
+    }
+
     fn ensure_config_dir() -> PathBuf {
         let config_path = config_dir()
             .map(|mut p| {
@@ -56,6 +164,10 @@ impl Config {
     }
 
     pub fn load() -> Self {
+        if let Some(path) = CONFIG_FILE.get() {
+            return Self::load_from(path);
+        }
+
         let config_path = Self::ensure_config_dir();
         let config_file = config_path.join("config.toml");
         let default_config = Config::default();
@@ -68,12 +180,35 @@ impl Config {
         }
 
         let config_str = fs::read_to_string(&config_file).unwrap_or_default();
-        let config: Config = toml::from_str(&config_str).unwrap_or_else(|_| default_config);
+        let config: Config = toml::from_str(&config_str).unwrap_or(default_config);
 
         config
     }
 
+    /// Reads a configuration file given on the command line, which unlike
+    /// the default one is never created or silently ignored.
+    fn load_from(path: &Path) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                error!("Failed to read config {:?}: {}", path, e);
+                return Config::default();
+            }
+        };
+        toml::from_str(&contents).unwrap_or_else(|e| {
+            error!("Failed to parse config {:?}: {}", path, e);
+            Config::default()
+        })
+    }
+
     pub fn load_css() -> String {
+        if let Some(path) = CSS_FILE.get() {
+            return fs::read_to_string(path).unwrap_or_else(|e| {
+                error!("Failed to read stylesheet {:?}: {}", path, e);
+                get_default_css(&Self::load())
+            });
+        }
+
         let config_path = Self::ensure_config_dir();
         let css_path = config_path.join("style.css");
         let config = Self::load();
@@ -107,6 +242,25 @@ fn get_default_css(config: &Config) -> String {
             background-color: #181818;
         }}
 
+        list row.app-action {{
+            margin-left: 32px;
+        }}
+
+        list row.marked {{
+            border-left: 3px solid #808080;
+        }}
+
+        .prompt {{
+            color: #a0a0a0;
+            font-size: 16px;
+            margin-left: 12px;
+        }}
+
+        .message {{
+            color: #a0a0a0;
+            margin: 0 12px 8px 12px;
+        }}
+
         entry {{
             margin: 12px;
             margin-bottom: 8px;
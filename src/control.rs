@@ -0,0 +1,247 @@
+//! The `org.hyprutils.Hyprlauncher` D-Bus interface, for scripting the
+//! running launcher from bars, binds and scripts.
+
+use crate::provider;
+use crate::search::SearchMode;
+use log::error;
+use std::sync::LazyLock;
+use tokio::runtime::Handle;
+use tokio::sync::{broadcast, mpsc};
+use zbus::object_server::SignalEmitter;
+use zbus::{Connection, fdo, interface, proxy};
+
+pub const BUS_NAME: &str = "org.hyprutils.Hyprlauncher";
+pub const OBJECT_PATH: &str = "/org/hyprutils/Hyprlauncher";
+
+/// Requests for the launcher window, received over D-Bus.
+pub enum Command {
+    Show,
+    Hide,
+    Toggle,
+    SetQuery(String),
+    SetMode(SearchMode),
+}
+
+/// Things the launcher window reports, sent out as D-Bus signals.
+#[derive(Clone)]
+pub enum Event {
+    /// An entry was launched, with its ID.
+    Launched(String),
+    /// The window was closed or hidden.
+    Closed,
+}
+
+static EVENTS: LazyLock<broadcast::Sender<Event>> = LazyLock::new(|| broadcast::Sender::new(16));
+
+/// Reports `event` to every D-Bus client listening for it.
+pub fn emit(event: Event) {
+    // Fails only when nobody is listening, e.g. before the interface is up.
+    let _ = EVENTS.send(event);
+}
+
+struct Control {
+    commands: mpsc::UnboundedSender<Command>,
+    /// The zbus executor runs outside of tokio, so searches are sent back
+    /// to the runtime the interface was started from.
+    runtime: Handle,
+}
+
+impl Control {
+    fn send(&self, command: Command) -> fdo::Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| fdo::Error::Failed(String::from("the launcher window is gone")))
+    }
+}
+
+#[interface(name = "org.hyprutils.Hyprlauncher")]
+impl Control {
+    fn show(&self) -> fdo::Result<()> {
+        self.send(Command::Show)
+    }
+
+    fn hide(&self) -> fdo::Result<()> {
+        self.send(Command::Hide)
+    }
+
+    fn toggle(&self) -> fdo::Result<()> {
+        self.send(Command::Toggle)
+    }
+
+    fn set_query(&self, query: String) -> fdo::Result<()> {
+        self.send(Command::SetQuery(query))
+    }
+
+    /// Switches to `all` or to the provider with this name.
+    fn set_mode(&self, mode: &str) -> fdo::Result<()> {
+        let mode = mode.parse().map_err(fdo::Error::InvalidArgs)?;
+        if provider::is_unknown(&mode) {
+            let message = format!("no search provider named {}", mode);
+            return Err(fdo::Error::InvalidArgs(message));
+        }
+        self.send(Command::SetMode(mode))
+    }
+
+    /// Ranked results for `query` from every provider, as (ID, name, icon
+    /// name).
+    async fn search(&self, query: String) -> fdo::Result<Vec<(String, String, String)>> {
+        let results = self
+            .runtime
+            .spawn(async move { provider::search_ranked(&query, &SearchMode::All).await })
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        Ok(results
+            .into_iter()
+            .map(|result| (result.app.id, result.app.name, result.app.icon_name))
+            .collect())
+    }
+
+    #[zbus(signal)]
+    async fn launched(emitter: &SignalEmitter<'_>, id: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn closed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
+
+/// Client side of the interface, for forwarding command-line requests to an
+/// instance that is already running.
+#[proxy(
+    interface = "org.hyprutils.Hyprlauncher",
+    default_service = "org.hyprutils.Hyprlauncher",
+    default_path = "/org/hyprutils/Hyprlauncher"
+)]
+trait Hyprlauncher {
+    fn show(&self) -> zbus::Result<()>;
+
+    fn set_query(&self, query: &str) -> zbus::Result<()>;
+
+    fn set_mode(&self, mode: &str) -> zbus::Result<()>;
+}
+
+/// Makes the running instance show `query` in `mode`.
+pub async fn show_remote(query: Option<&str>, mode: Option<SearchMode>) -> zbus::Result<()> {
+    let connection = Connection::session().await?;
+    let launcher = HyprlauncherProxy::new(&connection).await?;
+    if let Some(mode) = mode {
+        launcher.set_mode(&mode.to_string()).await?;
+    }
+    if let Some(query) = query {
+        launcher.set_query(query).await?;
+    }
+    launcher.show().await
+}
+
+/// Exports the interface on the session bus under [`BUS_NAME`]. Commands
+/// arrive on `commands`. The interface lives as long as the returned
+/// connection.
+pub async fn serve(commands: mpsc::UnboundedSender<Command>) -> zbus::Result<Connection> {
+    let connection = Connection::session().await?;
+    serve_on(&connection, commands).await?;
+    Ok(connection)
+}
+
+/// Does the work of [`serve`] on a connection the caller already has.
+pub async fn serve_on(
+    connection: &Connection,
+    commands: mpsc::UnboundedSender<Command>,
+) -> zbus::Result<()> {
+    let control = Control {
+        commands,
+        runtime: Handle::current(),
+    };
+    connection.object_server().at(OBJECT_PATH, control).await?;
+    connection.request_name(BUS_NAME).await?;
+
+    let emitter = SignalEmitter::new(connection, OBJECT_PATH)?.into_owned();
+    let mut events = EVENTS.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let result = match events.recv().await {
+                Ok(Event::Launched(id)) => Control::launched(&emitter, &id).await,
+                Ok(Event::Closed) => Control::closed(&emitter).await,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            if let Err(e) = result {
+                error!("Failed to emit D-Bus signal: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::PrivateBus;
+    use std::future::poll_fn;
+    use std::pin::Pin;
+    use zbus::export::futures_core::Stream;
+
+    /// The whole interface, signals included.
+    #[proxy(
+        interface = "org.hyprutils.Hyprlauncher",
+        default_service = "org.hyprutils.Hyprlauncher",
+        default_path = "/org/hyprutils/Hyprlauncher"
+    )]
+    trait Client {
+        fn show(&self) -> zbus::Result<()>;
+
+        fn hide(&self) -> zbus::Result<()>;
+
+        fn toggle(&self) -> zbus::Result<()>;
+
+        fn set_query(&self, query: &str) -> zbus::Result<()>;
+
+        fn set_mode(&self, mode: &str) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        fn launched(&self, id: &str) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        fn closed(&self) -> zbus::Result<()>;
+    }
+
+    async fn next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+        poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+    }
+
+    #[tokio::test]
+    async fn methods_become_commands_and_events_become_signals() {
+        let bus = PrivateBus::start();
+        let server = bus.connect().await;
+        let (tx, mut commands) = mpsc::unbounded_channel();
+        serve_on(&server, tx).await.unwrap();
+        let client = ClientProxy::new(&bus.connect().await).await.unwrap();
+
+        client.show().await.unwrap();
+        client.set_query("fire").await.unwrap();
+        client.set_mode("all").await.unwrap();
+        client.toggle().await.unwrap();
+        client.hide().await.unwrap();
+        assert!(matches!(commands.recv().await, Some(Command::Show)));
+        assert!(matches!(commands.recv().await, Some(Command::SetQuery(q)) if q == "fire"));
+        assert!(matches!(
+            commands.recv().await,
+            Some(Command::SetMode(SearchMode::All))
+        ));
+        assert!(matches!(commands.recv().await, Some(Command::Toggle)));
+        assert!(matches!(commands.recv().await, Some(Command::Hide)));
+
+        let error = client.set_mode("").await.unwrap_err();
+        assert!(
+            matches!(error, zbus::Error::MethodError(name, _, _) if name == "org.freedesktop.DBus.Error.InvalidArgs")
+        );
+
+        let mut launched = client.receive_launched().await.unwrap();
+        let mut closed = client.receive_closed().await.unwrap();
+        emit(Event::Launched(String::from("firefox.desktop")));
+        emit(Event::Closed);
+        let signal = next(&mut launched).await.unwrap();
+        assert_eq!(signal.args().unwrap().id, "firefox.desktop");
+        assert!(next(&mut closed).await.is_some());
+
+        drop(commands);
+        assert!(client.show().await.is_err());
+    }
+}
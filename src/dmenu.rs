@@ -0,0 +1,79 @@
+//! dmenu-compatible mode: lines read from stdin become the entries, and the
+//! chosen ones are printed to stdout.
+
+use crate::cli::PickerArgs;
+use crate::picker::{self, Choice, Page, Row};
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+use std::rc::Rc;
+
+/// The lines of `input`, with invalid UTF-8 replaced rather than ending the
+/// input early.
+fn read_lines(input: impl BufRead) -> Vec<String> {
+    input
+        .split(b'\n')
+        .map_while(Result::ok)
+        .map(|mut line| {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            String::from_utf8_lossy(&line).into_owned()
+        })
+        .collect()
+}
+
+/// Reads stdin, shows the picker and prints the choice. Exits with failure
+/// when the picker was cancelled.
+pub fn run(args: PickerArgs) -> ExitCode {
+    let rows = read_lines(io::stdin().lock())
+        .into_iter()
+        .map(Row::new)
+        .collect();
+    let mut page = Page::new(rows);
+    page.prompt = args.prompt.clone();
+
+    let chosen = Rc::new(RefCell::new(None));
+    let print_index = args.index;
+    picker::run(page, args.picker_options(), {
+        let chosen = chosen.clone();
+        move |page, choice| {
+            let lines: Vec<String> = match choice {
+                Choice::Rows(indices) if print_index => {
+                    indices.iter().map(|index| index.to_string()).collect()
+                }
+                Choice::Rows(indices) => indices
+                    .iter()
+                    .map(|&index| page.rows[index].text.clone())
+                    .collect(),
+                Choice::Text(_) if print_index => vec![String::from("-1")],
+                Choice::Text(text) => vec![text],
+            };
+            chosen.replace(Some(lines));
+            None
+        }
+    });
+
+    let Some(lines) = chosen.take() else {
+        return ExitCode::FAILURE;
+    };
+    let mut stdout = io::stdout().lock();
+    match lines
+        .iter()
+        .try_for_each(|line| writeln!(stdout, "{}", line))
+    {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(_) => ExitCode::FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_utf8_does_not_end_the_input() {
+        let input: &[u8] = b"first\r\nbad \xff line\nlast";
+        assert_eq!(read_lines(input), ["first", "bad \u{fffd} line", "last"]);
+    }
+}
@@ -0,0 +1,253 @@
+//! Parsing of the Desktop Entry `Exec` key.
+//!
+//! `Exec` values go through two levels of escaping: the generic string escapes
+//! every desktop entry value supports (`\s`, `\n`, `\t`, `\r`, `\\`), undone by
+//! [`unescape_value`] when the entry is loaded, and the shell-like quoting of
+//! the command line itself, undone by [`tokenize`].
+
+/// Values substituted for the field codes of an `Exec` line.
+#[derive(Default)]
+pub struct FieldCodes<'a> {
+    /// Translated application name, used for `%c`.
+    pub name: &'a str,
+    /// `Icon` key, expanded to `--icon <icon>` for `%i`.
+    pub icon: Option<&'a str>,
+    /// Location of the desktop file, used for `%k`.
+    pub desktop_file: Option<&'a str>,
+    /// Files or URLs to open, used for `%f`, `%F`, `%u` and `%U`.
+    pub files: &'a [String],
+}
+
+/// Reverses the string escapes shared by all desktop entry values.
+pub fn unescape_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Splits an unescaped `Exec` value into arguments.
+///
+/// Arguments are separated by spaces and may be enclosed in double quotes, in
+/// which case `\"`, `` \` ``, `\$` and `\\` stand for the literal character.
+/// Returns `None` for an unterminated quote.
+pub fn tokenize(exec: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            escaped @ ('"' | '`' | '$' | '\\') => current.push(escaped),
+                            other => {
+                                current.push('\\');
+                                current.push(other);
+                            }
+                        },
+                        other => current.push(other),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                current.push(chars.next().unwrap_or('\\'));
+            }
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                in_arg = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Some(args)
+}
+
+/// Tokenizes `exec` and expands its field codes into the final argv.
+///
+/// `%F`, `%U` and `%i` are only recognised as whole arguments, as the spec
+/// requires, and expand to any number of arguments. Deprecated and unknown
+/// codes are dropped. Returns `None` when the line is malformed or empty.
+pub fn expand(exec: &str, codes: &FieldCodes) -> Option<Vec<String>> {
+    let mut argv = Vec::new();
+    for arg in tokenize(exec)? {
+        match arg.as_str() {
+            "%F" | "%U" => argv.extend(codes.files.iter().cloned()),
+            "%i" => {
+                if let Some(icon) = codes.icon.filter(|icon| !icon.is_empty()) {
+                    argv.push(String::from("--icon"));
+                    argv.push(icon.to_string());
+                }
+            }
+            "%f" | "%u" if codes.files.is_empty() => {}
+            _ => argv.push(expand_inline(&arg, codes)),
+        }
+    }
+    (!argv.is_empty()).then_some(argv)
+}
+
+fn expand_inline(arg: &str, codes: &FieldCodes) -> String {
+    let mut result = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => result.push('%'),
+            Some('f' | 'u') => {
+                if let Some(file) = codes.files.first() {
+                    result.push_str(file);
+                }
+            }
+            Some('c') => result.push_str(codes.name),
+            Some('k') => result.push_str(codes.desktop_file.unwrap_or("")),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Quotes `arg` so that [`expand`] yields it back as a single literal
+/// argument.
+pub fn quote(arg: &str) -> String {
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for c in arg.chars() {
+        match c {
+            '"' | '`' | '$' | '\\' => quoted.push('\\'),
+            '%' => quoted.push('%'),
+            _ => {}
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn unescape_value_reverses_string_escapes() {
+        assert_eq!(unescape_value(r"a\sb\tc\\d"), "a b\tc\\d");
+        assert_eq!(unescape_value(r"\x"), r"\x");
+        assert_eq!(unescape_value("trailing\\"), "trailing\\");
+    }
+
+    #[test]
+    fn tokenize_splits_on_whitespace() {
+        assert_eq!(
+            tokenize("  foo  --bar baz "),
+            Some(args(&["foo", "--bar", "baz"]))
+        );
+        assert_eq!(tokenize(""), Some(Vec::new()));
+    }
+
+    #[test]
+    fn tokenize_handles_quotes_and_escapes() {
+        assert_eq!(
+            tokenize(r#"sh -c "echo \"\$HOME\" \`x\` \\ \n""#),
+            Some(args(&["sh", "-c", r#"echo "$HOME" `x` \ \n"#]))
+        );
+        assert_eq!(tokenize(r#""""#), Some(args(&[""])));
+        assert_eq!(tokenize(r#"a"b c"d"#), Some(args(&["ab cd"])));
+        assert_eq!(tokenize(r"a\ b"), Some(args(&["a b"])));
+    }
+
+    #[test]
+    fn tokenize_rejects_unterminated_quotes() {
+        assert_eq!(tokenize(r#"foo "bar"#), None);
+        assert_eq!(tokenize(r#"foo "bar\"#), None);
+    }
+
+    #[test]
+    fn expand_substitutes_field_codes() {
+        let files = args(&["/tmp/a b.txt", "/tmp/c.txt"]);
+        let codes = FieldCodes {
+            name: "Editor",
+            icon: Some("editor"),
+            desktop_file: Some("/usr/share/applications/editor.desktop"),
+            files: &files,
+        };
+        assert_eq!(
+            expand("editor %F", &codes),
+            Some(args(&["editor", "/tmp/a b.txt", "/tmp/c.txt"]))
+        );
+        assert_eq!(
+            expand("editor --file=%f", &codes),
+            Some(args(&["editor", "--file=/tmp/a b.txt"]))
+        );
+        assert_eq!(
+            expand("editor %i --title=%c %k 100%%", &codes),
+            Some(args(&[
+                "editor",
+                "--icon",
+                "editor",
+                "--title=Editor",
+                "/usr/share/applications/editor.desktop",
+                "100%",
+            ]))
+        );
+    }
+
+    #[test]
+    fn expand_drops_unused_and_deprecated_codes() {
+        let codes = FieldCodes::default();
+        assert_eq!(
+            expand("app %U %f %i --dir=%d%m", &codes),
+            Some(args(&["app", "--dir="]))
+        );
+        assert_eq!(expand("app %u", &codes), Some(args(&["app"])));
+        assert_eq!(expand("%F", &codes), None);
+        assert_eq!(expand("app \"unterminated", &codes), None);
+    }
+
+    #[test]
+    fn quote_round_trips_through_expand() {
+        for arg in ["plain", "with space", r#"q"u`o$t\e"#, "100% %f", ""] {
+            let exec = format!("app {}", quote(arg));
+            assert_eq!(
+                expand(&exec, &FieldCodes::default()),
+                Some(args(&["app", arg]))
+            );
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Launching through Hyprland's IPC socket, so window rules can be attached
+//! to the new window.
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Location of the command socket of the running Hyprland instance.
+pub fn socket_path() -> Option<PathBuf> {
+    let runtime_dir = dirs::runtime_dir()?;
+    let signature = std::env::var("HYPRLAND_INSTANCE_SIGNATURE").ok()?;
+    Some(
+        runtime_dir
+            .join("hypr")
+            .join(signature)
+            .join(".socket.sock"),
+    )
+}
+
+/// Runs the shell `command` through `dispatch exec`, applying `rules` such as
+/// `workspace 3 silent` or `float` to the window it opens.
+pub fn exec(rules: &[String], command: &str) -> io::Result<()> {
+    let socket = socket_path().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "HYPRLAND_INSTANCE_SIGNATURE is not set, is Hyprland running?",
+        )
+    })?;
+    dispatch_on(&socket, &exec_request(rules, command))
+}
+
+/// Formats the `dispatch exec` request for [`exec`].
+pub fn exec_request(rules: &[String], command: &str) -> String {
+    if rules.is_empty() {
+        format!("dispatch exec {}", command)
+    } else {
+        format!("dispatch exec [{}] {}", rules.join(";"), command)
+    }
+}
+
+/// Sends one request to the socket at `socket` and checks that Hyprland
+/// answered `ok`.
+pub fn dispatch_on(socket: &Path, request: &str) -> io::Result<()> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.write_all(request.as_bytes())?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    match reply.trim() {
+        "ok" => Ok(()),
+        error => Err(io::Error::other(format!(
+            "Hyprland rejected {:?}: {}",
+            request, error
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::os::unix::net::UnixListener;
+    use std::thread::{self, JoinHandle};
+
+    /// Serves one request on a socket in `dir` like Hyprland does, answering
+    /// `reply`. The handle yields the request received.
+    fn fake_hyprland(dir: &TempDir, reply: &'static str) -> (PathBuf, JoinHandle<String>) {
+        let socket = dir.path().join(".socket.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let len = stream.read(&mut request).unwrap();
+            stream.write_all(reply.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request[..len]).into_owned()
+        });
+        (socket, server)
+    }
+
+    #[test]
+    fn dispatch_sends_the_request_and_accepts_ok() {
+        let dir = TempDir::new();
+        let (socket, server) = fake_hyprland(&dir, "ok");
+        let rules = [String::from("workspace 3 silent"), String::from("float")];
+        let request = exec_request(&rules, "'firefox' '--new-window'");
+
+        dispatch_on(&socket, &request).unwrap();
+        assert_eq!(
+            server.join().unwrap(),
+            "dispatch exec [workspace 3 silent;float] 'firefox' '--new-window'"
+        );
+    }
+
+    #[test]
+    fn dispatch_reports_rejected_requests() {
+        let dir = TempDir::new();
+        let (socket, server) = fake_hyprland(&dir, "Invalid dispatcher");
+
+        let error = dispatch_on(&socket, "dispatch bogus").unwrap_err();
+        assert!(error.to_string().contains("Invalid dispatcher"));
+        assert_eq!(server.join().unwrap(), "dispatch bogus");
+    }
+
+    #[test]
+    fn dispatch_fails_without_a_socket() {
+        let dir = TempDir::new();
+        assert!(dispatch_on(&dir.path().join(".socket.sock"), "dispatch exec true").is_err());
+    }
+
+    #[test]
+    fn exec_request_omits_empty_rules() {
+        assert_eq!(exec_request(&[], "kitty"), "dispatch exec kitty");
+    }
+}
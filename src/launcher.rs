@@ -1,29 +1,155 @@
-use freedesktop_entry_parser::parse_entry;
+use crate::cache::{self, IndexCache, Signatures};
+use crate::config::Config;
+use crate::exec;
+use crate::watcher;
+use freedesktop_entry_parser::{AttrSelector, parse_entry};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
-use std::sync::OnceLock;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, LazyLock, Mutex};
 use tokio::sync::RwLock;
 use walkdir::WalkDir;
 
-pub static APP_CACHE: OnceLock<RwLock<HashMap<String, AppEntry>>> = OnceLock::new();
+/// The application index, keyed by [`AppEntry::id`]. Filled by
+/// [`load_applications`] and kept current by [`refresh_entries`].
+pub static APP_CACHE: LazyLock<RwLock<HashMap<String, AppEntry>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
 
-#[derive(Clone, Serialize, Deserialize)]
+/// Set once [`APP_CACHE`] holds the whole index, checked against the
+/// directories. Saving it any earlier would write a partial index that the
+/// next start takes as current.
+static LOADED: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct AppEntry {
+    /// Stable key for the entry: the desktop-file ID for `.desktop` files, the
+    /// binary name for `PATH` executables and the full path for files.
+    pub id: String,
     pub name: String,
+    #[serde(default)]
+    pub generic_name: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
     pub exec: String,
     pub icon_name: String,
     pub path: String,
     pub launch_count: u32,
     pub entry_type: EntryType,
+    #[serde(default)]
+    pub actions: Vec<AppAction>,
+    /// Set by `Terminal=true`: the program needs a terminal emulator.
+    #[serde(default)]
+    pub terminal: bool,
+    /// Working directory from the `Path` key.
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    /// Set by `PrefersNonDefaultGPU=true`: run on the discrete GPU if any.
+    #[serde(default)]
+    pub prefers_non_default_gpu: bool,
+    /// Set by `DBusActivatable=true`: start through `org.freedesktop.Application`.
+    #[serde(default)]
+    pub dbus_activatable: bool,
+    /// Set by `StartupNotify=true`: the app reports when it finished starting.
+    #[serde(default)]
+    pub startup_notify: bool,
+    /// `StartupWMClass`, the class or app ID the app's windows will carry.
+    #[serde(default)]
+    pub startup_wm_class: Option<String>,
 }
 
+/// A `[Desktop Action <id>]` group declared through the `Actions` key.
 #[derive(Clone, Serialize, Deserialize)]
+pub struct AppAction {
+    pub id: String,
+    pub name: String,
+    pub exec: String,
+    pub icon_name: Option<String>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub enum EntryType {
+    #[default]
     Application,
+    /// A desktop action of the application whose ID prefixes the entry ID.
+    Action,
+    /// An executable found in `PATH`.
+    Binary,
     File,
+    /// A result of the search provider with this name, which also decides
+    /// what activating it does.
+    Provider(String),
+    /// An action of a [`EntryType::Provider`] result, ID made up like the ID
+    /// of an [`EntryType::Action`].
+    ProviderAction(String),
+}
+
+/// Separates the application ID from the action ID in action entry IDs.
+const ACTION_ID_SEPARATOR: char = ':';
+
+impl AppEntry {
+    /// Builds a launchable entry for one of this application's actions.
+    pub fn action_entry(&self, action: &AppAction) -> AppEntry {
+        AppEntry {
+            id: format!("{}{}{}", self.id, ACTION_ID_SEPARATOR, action.id),
+            name: format!("{} — {}", self.name, action.name),
+            exec: action.exec.clone(),
+            icon_name: action
+                .icon_name
+                .clone()
+                .unwrap_or_else(|| self.icon_name.clone()),
+            path: self.path.clone(),
+            launch_count: self.launch_count,
+            entry_type: match &self.entry_type {
+                EntryType::Provider(name) => EntryType::ProviderAction(name.clone()),
+                _ => EntryType::Action,
+            },
+            terminal: self.terminal,
+            working_dir: self.working_dir.clone(),
+            prefers_non_default_gpu: self.prefers_non_default_gpu,
+            dbus_activatable: self.dbus_activatable,
+            startup_notify: self.startup_notify,
+            startup_wm_class: self.startup_wm_class.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether launching this entry counts towards its ranking. Only
+    /// applications and binaries are ranked by launch count.
+    pub fn counts_launches(&self) -> bool {
+        matches!(
+            self.entry_type,
+            EntryType::Application | EntryType::Action | EntryType::Binary
+        )
+    }
+
+    /// ID of the application this entry belongs to. Actions count towards
+    /// their application's launches.
+    pub fn app_id(&self) -> &str {
+        match self.entry_type {
+            EntryType::Action | EntryType::ProviderAction(_) => self
+                .id
+                .rsplit_once(ACTION_ID_SEPARATOR)
+                .map_or(self.id.as_str(), |(app_id, _)| app_id),
+            _ => &self.id,
+        }
+    }
+
+    /// ID of the desktop action this entry launches, if it is one.
+    pub fn action_id(&self) -> Option<&str> {
+        match self.entry_type {
+            EntryType::Action | EntryType::ProviderAction(_) => self
+                .id
+                .rsplit_once(ACTION_ID_SEPARATOR)
+                .map(|(_, action_id)| action_id),
+            _ => None,
+        }
+    }
 }
 
 pub static HEATMAP_PATH: &str = "~/.local/share/hyprlauncher/heatmap.toml";
@@ -32,13 +158,10 @@ pub async fn increment_launch_count(app: &AppEntry) {
     // Log the start of the operation
     info!("Starting increment_launch_count for app: {}", app.name);
 
-    // Initialize the cache if it hasn't been already
-    let cache = APP_CACHE.get_or_init(|| RwLock::new(HashMap::new()));
-
     info!("Acquiring write lock on cache");
-    let mut cache = cache.write().await;
+    let mut cache = APP_CACHE.write().await;
 
-    if let Some(entry) = cache.get_mut(&app.name) {
+    if let Some(entry) = cache.get_mut(app.app_id()) {
         info!(
             "Found cache entry for app: {}, current count: {}",
             app.name, entry.launch_count
@@ -51,14 +174,11 @@ pub async fn increment_launch_count(app: &AppEntry) {
             app.name, count
         );
 
-        // Clone the name to avoid lifetime issues
-        let app_name = app.name.clone();
+        // Clone the id to avoid lifetime issues
+        let app_id = app.app_id().to_string();
 
-        info!(
-            "Spawning blocking task to save heatmap for app: {}",
-            app_name
-        );
-        tokio::task::spawn_blocking(move || save_heatmap(&app_name, count));
+        info!("Spawning blocking task to save heatmap for app: {}", app_id);
+        tokio::task::spawn_blocking(move || save_heatmap(&app_id, count));
     } else {
         // Log when the app isn't found in cache
         info!("No cache entry found for app: {}", app.name);
@@ -70,15 +190,27 @@ pub struct Heatmap {
     map: HashMap<String, u32>,
 }
 
+impl Heatmap {
+    /// Looks up the launch count for `id`, falling back to the display name
+    /// older versions used as the key so existing counts carry over.
+    fn count(&self, id: &str, legacy_name: &str) -> u32 {
+        self.map
+            .get(id)
+            .or_else(|| self.map.get(legacy_name))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
 use log::{debug, error, info};
 
-fn save_heatmap(name: &str, count: u32) {
+fn save_heatmap(id: &str, count: u32) {
     let path = shellexpand::tilde(HEATMAP_PATH).to_string();
     let path = std::path::Path::new(&path);
 
     info!(
         "Saving heatmap for {} with count {} to path {:?}",
-        name, count, path
+        id, count, path
     );
 
     // Ensure directory and file exist
@@ -92,15 +224,15 @@ fn save_heatmap(name: &str, count: u32) {
 
     // Create file if it doesn't exist
     if !path.exists() {
-        match std::fs::File::create(&path) {
+        match std::fs::File::create(path) {
             Ok(_) => info!("Created new heatmap file at {:?}", path),
             Err(e) => error!("Failed to create heatmap file at {:?}: {}", path, e),
         }
     }
 
     let mut heatmap = load_heatmap();
-    heatmap.map.insert(name.to_string(), count);
-    info!("Updated heatmap: {} = {}", name, count);
+    heatmap.map.insert(id.to_string(), count);
+    info!("Updated heatmap: {} = {}", id, count);
 
     if let Ok(contents) = toml::to_string(&heatmap) {
         if let Err(e) = fs::write(path, &contents) {
@@ -128,7 +260,7 @@ fn load_heatmap() -> Heatmap {
                 debug!("Created directory {:?}", dir);
             }
         }
-        match std::fs::File::create(&path) {
+        match std::fs::File::create(path) {
             Ok(_) => info!("Created new empty heatmap file at {:?}", path),
             Err(e) => error!("Failed to create heatmap file at {:?}: {}", path, e),
         }
@@ -155,65 +287,103 @@ fn load_heatmap() -> Heatmap {
     }
 }
 
+/// Fills [`APP_CACHE`]. The on-disk index is used right away when it is
+/// valid; directories that changed since it was written are rescanned in the
+/// background afterwards.
 pub async fn load_applications() {
+    load(false).await;
+}
+
+/// Like [`load_applications`], but also waits for the rescan, for one-shot
+/// commands that exit right after.
+pub async fn load_current_applications() {
+    load(true).await;
+}
+
+async fn load(wait_for_rescan: bool) {
     info!("loading applications");
-    let heatmap = tokio::task::spawn_blocking(load_heatmap)
-        .await
-        .unwrap_or_default();
-    info!("heatmap loaded {:?}", heatmap);
-    let mut apps = HashMap::new();
-    let desktop_paths = [
-        "/usr/share/applications",
-        "/usr/local/share/applications",
-        "~/.local/share/applications",
-    ];
-
-    for path in desktop_paths {
-        let expanded_path = shellexpand::tilde(path).to_string();
-        if let Ok(entries) = std::fs::read_dir(expanded_path) {
-            for entry in entries.filter_map(|e| e.ok()) {
-                if let Some(name) = entry.file_name().to_str() {
-                    if name.ends_with(".desktop") {
-                        if let Ok(desktop_entry) = parse_entry(entry.path()) {
-                            if let Some(app_name) =
-                                desktop_entry.section("Desktop Entry").attr("Name")
-                            {
-                                let exec = desktop_entry
-                                    .section("Desktop Entry")
-                                    .attr("Exec")
-                                    .unwrap_or("")
-                                    .to_string();
-                                let icon = desktop_entry
-                                    .section("Desktop Entry")
-                                    .attr("Icon")
-                                    .unwrap_or("application-x-executable")
-                                    .to_string();
-                                let launch_count =
-                                    heatmap.map.get(app_name).copied().unwrap_or_default();
-
-                                apps.insert(
-                                    app_name.to_string(),
-                                    AppEntry {
-                                        name: app_name.to_string(),
-                                        exec,
-                                        icon_name: icon,
-                                        path: entry.path().to_string_lossy().to_string(),
-                                        launch_count,
-                                        entry_type: EntryType::Application,
-                                    },
-                                );
-                            }
-                        }
-                    }
-                }
-            }
+    let cached = tokio::task::spawn_blocking(|| {
+        let context = LoadContext::new();
+        let mut cached = cache::load(&context.fingerprint())?;
+        for app in cached.apps.values_mut() {
+            app.launch_count = context.heatmap.count(&app.id, &app.name);
         }
+        Some(cached)
+    })
+    .await
+    .ok()
+    .flatten();
+
+    let Some(cached) = cached else {
+        let apps = tokio::task::spawn_blocking(scan_applications)
+            .await
+            .unwrap_or_default();
+        *APP_CACHE.write().await = apps;
+        set_loaded();
+        return;
+    };
+
+    info!("loaded {} entries from the index cache", cached.apps.len());
+    *APP_CACHE.write().await = cached.apps;
+    let rescan = tokio::task::spawn_blocking(move || {
+        if revalidate(&cached.signatures) {
+            watcher::index_changed();
+        }
+        set_loaded();
+    });
+    if wait_for_rescan {
+        let _ = rescan.await;
+    }
+}
+
+fn set_loaded() {
+    *LOADED.0.lock().unwrap() = true;
+    LOADED.1.notify_all();
+}
+
+/// Blocks until the index is loaded and revalidated, after which it can be
+/// changed and saved.
+pub fn wait_until_loaded() {
+    let mut loaded = LOADED.0.lock().unwrap();
+    while !*loaded {
+        loaded = LOADED.1.wait(loaded).unwrap();
+    }
+}
+
+/// Looks up an entry by the ID it is listed under: a desktop-file ID, an
+/// action ID like `firefox.desktop:new-window`, a `PATH` binary or a file.
+pub async fn find_entry(id: &str) -> Option<AppEntry> {
+    let cache = APP_CACHE.read().await;
+    if let Some(app) = cache.get(id) {
+        return Some(app.clone());
+    }
+    if let Some((app_id, action_id)) = id.rsplit_once(ACTION_ID_SEPARATOR)
+        && let Some(app) = cache.get(app_id)
+        && let Some(action) = app.actions.iter().find(|action| action.id == action_id)
+    {
+        return Some(app.action_entry(action));
     }
+    Path::new(id)
+        .exists()
+        .then(|| create_file_entry(id.to_string()))
+        .flatten()
+}
 
-    let path = std::env::var("PATH").unwrap_or_default();
-    let path_entries: Vec<_> = path.split(':').collect();
+fn scan_applications() -> HashMap<String, AppEntry> {
+    let context = LoadContext::new();
+    let app_dirs = application_dirs();
+    let path_dirs = path_dirs();
+    // Taken before scanning so changes made during the scan are caught by
+    // the next revalidation.
+    let signatures = cache::signatures(&app_dirs, &path_dirs);
+    let mut apps = HashMap::new();
+    for (id, path) in desktop_files(&app_dirs) {
+        if let Some(app) = parse_desktop_file(&id, &path, &context) {
+            apps.insert(id, app);
+        }
+    }
 
-    let results: Vec<_> = path_entries
+    let results: Vec<_> = path_dirs
         .par_iter()
         .flat_map(|path_entry| {
             WalkDir::new(path_entry)
@@ -221,68 +391,465 @@ pub async fn load_applications() {
                 .max_depth(1)
                 .into_iter()
                 .filter_map(|e| e.ok())
-                .filter(|e| {
-                    e.file_type().is_file()
-                        && e.metadata()
-                            .map(|m| m.permissions().mode() & 0o111 != 0)
-                            .unwrap_or(false)
-                })
+                .filter(|e| e.file_type().is_file() && is_executable(e.path()))
                 .filter_map(|entry| {
-                    entry.file_name().to_str().map(|name| {
-                        let name = name.to_string();
-                        let path = entry.path().to_string_lossy().to_string();
-                        let launch_count = heatmap.map.get(&name).copied().unwrap_or_default();
-
-                        let icon_name = find_desktop_entry(&name)
-                            .map(|e| e.icon_name)
-                            .unwrap_or_else(|| "application-x-executable".to_string());
-
-                        (
-                            name.clone(),
-                            AppEntry {
-                                name,
-                                exec: path.clone(),
-                                icon_name,
-                                path,
-                                launch_count,
-                                entry_type: EntryType::Application,
-                            },
-                        )
-                    })
+                    let name = entry.file_name().to_str()?.to_string();
+                    let app = binary_entry(&name, entry.path(), &apps, &context);
+                    Some((name, app))
                 })
                 .collect::<Vec<_>>()
         })
         .collect();
 
+    // Earlier `PATH` entries shadow later ones, just like the shell does.
     for (name, entry) in results {
-        apps.insert(name, entry);
+        apps.entry(name).or_insert(entry);
+    }
+
+    cache::save(&IndexCache {
+        fingerprint: context.fingerprint(),
+        signatures,
+        apps: apps.clone(),
+    });
+    apps
+}
+
+/// Rescans the directories whose signature differs from `signatures`.
+/// Returns whether any entry changed.
+fn revalidate(signatures: &Signatures) -> bool {
+    let current = cache::signatures(&application_dirs(), &path_dirs());
+    let stale: Vec<_> = current
+        .iter()
+        .filter(|(dir, signature)| signatures.get(*dir) != Some(signature))
+        .map(|(dir, _)| dir)
+        .collect();
+    if stale.is_empty() {
+        return false;
     }
+    info!("Index cache is stale for {:?}", stale);
 
-    APP_CACHE.set(RwLock::new(apps));
+    // Everything the directory contains now, plus everything it used to
+    // provide, so removed files are dropped as well.
+    let mut changed: Vec<PathBuf> = APP_CACHE
+        .blocking_read()
+        .values()
+        .map(|app| PathBuf::from(&app.path))
+        .filter(|path| stale.iter().any(|dir| path.starts_with(dir)))
+        .collect();
+    for dir in stale {
+        changed.extend(
+            WalkDir::new(dir)
+                .follow_links(true)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.into_path()),
+        );
+    }
+    refresh_entries(&changed)
 }
 
-struct DesktopEntry {
-    icon_name: String,
+/// Updates the index for files that were added, changed or removed in an
+/// `applications` or `PATH` directory. Each affected ID is resolved again
+/// across all directories so precedence and shadowing stay correct.
+///
+/// Returns whether any entry changed.
+pub fn refresh_entries(changed: &[PathBuf]) -> bool {
+    let app_dirs = application_dirs();
+    let path_dirs = path_dirs();
+    let mut desktop_files = HashMap::new();
+    let mut binaries = HashSet::new();
+
+    for path in changed {
+        if let Some(root) = app_dirs.iter().find(|dir| path.starts_with(dir)) {
+            if let Some(id) = desktop_file_id(root, path)
+                && let Ok(relative) = path.strip_prefix(root)
+            {
+                desktop_files.insert(id, relative.to_path_buf());
+            }
+        } else if let Some(parent) = path.parent()
+            && path_dirs.iter().any(|dir| dir == parent)
+            && let Some(name) = path.file_name().and_then(|name| name.to_str())
+        {
+            binaries.insert(name.to_string());
+        }
+    }
+
+    if desktop_files.is_empty() && binaries.is_empty() {
+        return false;
+    }
+
+    let context = LoadContext::new();
+    // Parsed before taking the lock, which searches wait for.
+    let desktop_entries: Vec<_> = desktop_files
+        .into_iter()
+        .map(|(id, relative)| {
+            // Only the highest-precedence file counts, even if it is hidden.
+            let winner = app_dirs
+                .iter()
+                .flat_map(|dir| [dir.join(&relative), dir.join(&id)])
+                .find(|candidate| candidate.is_file());
+            info!("Refreshing index entry {}", id);
+            let app = winner.and_then(|winner| parse_desktop_file(&id, &winner, &context));
+            (id, app)
+        })
+        .collect();
+
+    let mut apps = APP_CACHE.blocking_write();
+    for (id, app) in desktop_entries {
+        match app {
+            Some(app) => apps.insert(id, app),
+            None => apps.remove(&id),
+        };
+    }
+    // Binaries go second so they pick up icons from refreshed desktop files.
+    for name in binaries {
+        let binary = path_dirs
+            .iter()
+            .map(|dir| dir.join(&name))
+            .find(|candidate| is_executable(candidate));
+        info!("Refreshing index entry {}", name);
+        match binary.map(|binary| binary_entry(&name, &binary, &apps, &context)) {
+            Some(app) => apps.insert(name, app),
+            None => apps.remove(&name),
+        };
+    }
+    let snapshot = apps.clone();
+    drop(apps);
+
+    cache::save(&IndexCache {
+        fingerprint: context.fingerprint(),
+        signatures: cache::signatures(&app_dirs, &path_dirs),
+        apps: snapshot,
+    });
+    true
+}
+
+/// Returns the directories listed in `PATH`, in lookup order.
+pub fn path_dirs() -> Vec<PathBuf> {
+    std::env::var("PATH")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Builds the entry for a `PATH` executable, borrowing the icon of the
+/// desktop entry with the same name from `apps` when there is one.
+fn binary_entry(
+    name: &str,
+    path: &Path,
+    apps: &HashMap<String, AppEntry>,
+    context: &LoadContext,
+) -> AppEntry {
+    let path = path.to_string_lossy().to_string();
+    let icon_name = apps
+        .get(&format!("{}.desktop", name))
+        .map(|app| app.icon_name.clone())
+        .unwrap_or_else(|| "application-x-executable".to_string());
+
+    AppEntry {
+        id: name.to_string(),
+        name: name.to_string(),
+        exec: exec::quote(&path),
+        icon_name,
+        path,
+        launch_count: context.heatmap.count(name, name),
+        entry_type: EntryType::Binary,
+        ..Default::default()
+    }
+}
+
+/// Returns every `applications` directory to scan, highest precedence first.
+///
+/// Follows `XDG_DATA_HOME` and `XDG_DATA_DIRS` as described by the XDG Base
+/// Directory spec, then appends the Flatpak, Snap and Nix export locations in
+/// case the session did not put them on `XDG_DATA_DIRS`.
+pub fn application_dirs() -> Vec<PathBuf> {
+    let home = dirs::home_dir().unwrap_or_default();
+    let mut data_dirs = vec![dirs::data_dir().unwrap_or_else(|| home.join(".local/share"))];
+
+    let xdg_data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| String::from("/usr/local/share:/usr/share"));
+    data_dirs.extend(
+        xdg_data_dirs
+            .split(':')
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute()),
+    );
+
+    data_dirs.extend([
+        home.join(".local/share/flatpak/exports/share"),
+        PathBuf::from("/var/lib/flatpak/exports/share"),
+        PathBuf::from("/var/lib/snapd/desktop"),
+        home.join(".nix-profile/share"),
+        PathBuf::from("/run/current-system/sw/share"),
+    ]);
+    if let Ok(user) = std::env::var("USER") {
+        data_dirs.push(PathBuf::from(format!(
+            "/etc/profiles/per-user/{}/share",
+            user
+        )));
+    }
+
+    let mut seen = HashSet::new();
+    data_dirs
+        .into_iter()
+        .map(|dir| dir.join("applications"))
+        .filter(|dir| seen.insert(dir.clone()))
+        .collect()
 }
 
-fn find_desktop_entry(name: &str) -> Option<DesktopEntry> {
-    let paths = [
-        "/usr/share/applications",
-        "/usr/local/share/applications",
-        "~/.local/share/applications",
-    ];
+/// Derives the desktop-file ID of `path` relative to the `applications`
+/// directory `root`, turning subdirectory separators into `-`.
+pub fn desktop_file_id(root: &Path, path: &Path) -> Option<String> {
+    if path.extension().and_then(|ext| ext.to_str()) != Some("desktop") {
+        return None;
+    }
+    let relative = path.strip_prefix(root).ok()?.to_str()?;
+    Some(relative.replace('/', "-"))
+}
 
-    for path in paths {
-        let desktop_file = format!("{}/{}.desktop", path, name);
-        if let Ok(entry) = parse_entry(&desktop_file) {
-            if let Some(icon) = entry.section("Desktop Entry").attr("Icon") {
-                return Some(DesktopEntry {
-                    icon_name: icon.to_string(),
-                });
+/// Lists the desktop files in `app_dirs` with their IDs. Directories are
+/// ordered by precedence, so the first file seen for an ID shadows any later
+/// ones.
+fn desktop_files(app_dirs: &[PathBuf]) -> Vec<(String, PathBuf)> {
+    let mut seen_ids = HashSet::new();
+    let mut files = Vec::new();
+    for dir in app_dirs {
+        for entry in WalkDir::new(dir)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            let Some(id) = desktop_file_id(dir, entry.path()) else {
+                continue;
+            };
+            if seen_ids.insert(id.clone()) {
+                files.push((id, entry.into_path()));
             }
         }
     }
-    None
+    files
+}
+
+/// Bump whenever [`AppEntry`] or the way it is built changes, so cached
+/// indexes from older versions are rebuilt.
+const INDEX_FORMAT_VERSION: u32 = 4;
+
+/// Settings shared by every desktop file parsed during one index build.
+struct LoadContext {
+    heatmap: Heatmap,
+    show_hidden: bool,
+    current_desktops: Vec<String>,
+    locales: Vec<String>,
+}
+
+impl LoadContext {
+    fn new() -> Self {
+        let heatmap = load_heatmap();
+        info!("heatmap loaded {:?}", heatmap);
+        Self {
+            heatmap,
+            show_hidden: Config::load().show_hidden_entries,
+            current_desktops: current_desktops(),
+            locales: locale_variants(&current_locale()),
+        }
+    }
+
+    /// Identifies the inputs besides file contents that an index built with
+    /// this context depends on.
+    fn fingerprint(&self) -> String {
+        format!(
+            "v{} {:?} {:?} {:?} {:?} {}",
+            INDEX_FORMAT_VERSION,
+            application_dirs(),
+            path_dirs(),
+            self.locales,
+            self.current_desktops,
+            self.show_hidden
+        )
+    }
+}
+
+fn parse_desktop_file(id: &str, path: &Path, context: &LoadContext) -> Option<AppEntry> {
+    let desktop_entry = parse_entry(path).ok()?;
+    let section = desktop_entry.section("Desktop Entry");
+    if !context.show_hidden && !should_show(&section, &context.current_desktops) {
+        return None;
+    }
+    let locales = &context.locales;
+    let name = localized(&section, "Name", locales)?.to_string();
+    let generic_name = localized(&section, "GenericName", locales).map(str::to_string);
+    let comment = localized(&section, "Comment", locales).map(str::to_string);
+    let keywords = localized(&section, "Keywords", locales)
+        .unwrap_or("")
+        .split(';')
+        .filter(|keyword| !keyword.is_empty())
+        .map(str::to_string)
+        .collect();
+    let exec = exec::unescape_value(section.attr("Exec").unwrap_or(""));
+    let icon_name = section
+        .attr("Icon")
+        .unwrap_or("application-x-executable")
+        .to_string();
+    let launch_count = context.heatmap.count(id, &name);
+
+    let actions = section
+        .attr("Actions")
+        .unwrap_or("")
+        .split(';')
+        .filter(|action| !action.is_empty())
+        .filter_map(|action| {
+            let group = desktop_entry.section(format!("Desktop Action {}", action));
+            Some(AppAction {
+                id: action.to_string(),
+                name: localized(&group, "Name", locales)?.to_string(),
+                exec: exec::unescape_value(group.attr("Exec")?),
+                icon_name: group.attr("Icon").map(str::to_string),
+            })
+        })
+        .collect();
+
+    Some(AppEntry {
+        id: id.to_string(),
+        name,
+        generic_name,
+        comment,
+        keywords,
+        exec,
+        icon_name,
+        path: path.to_string_lossy().to_string(),
+        launch_count,
+        actions,
+        terminal: section.attr("Terminal") == Some("true"),
+        working_dir: section
+            .attr("Path")
+            .filter(|dir| !dir.is_empty())
+            .map(str::to_string),
+        prefers_non_default_gpu: section.attr("PrefersNonDefaultGPU") == Some("true"),
+        dbus_activatable: section.attr("DBusActivatable") == Some("true"),
+        startup_notify: section.attr("StartupNotify") == Some("true"),
+        startup_wm_class: section.attr("StartupWMClass").map(str::to_string),
+        ..Default::default()
+    })
+}
+
+/// Returns the message locale set through `LC_ALL`, `LC_MESSAGES` or `LANG`.
+fn current_locale() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+}
+
+/// Returns the `key[locale]` variants to try for `locale`, most specific
+/// first, as the Desktop Entry spec describes for
+/// `lang_COUNTRY.ENCODING@MODIFIER` values.
+fn locale_variants(locale: &str) -> Vec<String> {
+    let (locale, modifier) = match locale.split_once('@') {
+        Some((locale, modifier)) => (locale, Some(modifier)),
+        None => (locale, None),
+    };
+    let locale = locale.split('.').next().unwrap_or("");
+    let (lang, country) = match locale.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (locale, None),
+    };
+    if lang.is_empty() || lang == "C" || lang == "POSIX" {
+        return Vec::new();
+    }
+
+    let mut variants = Vec::new();
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        variants.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+    if let Some(country) = country {
+        variants.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = modifier {
+        variants.push(format!("{}@{}", lang, modifier));
+    }
+    variants.push(lang.to_string());
+    variants
+}
+
+/// Reads `key` from `section`, preferring the first matching localized value.
+fn localized<'a, T: AsRef<str>>(
+    section: &'a AttrSelector<'a, T>,
+    key: &str,
+    locales: &[String],
+) -> Option<&'a str> {
+    locales
+        .iter()
+        .find_map(|locale| section.attr_with_param(key, locale))
+        .or_else(|| section.attr(key))
+}
+
+/// Returns the desktop names listed in `XDG_CURRENT_DESKTOP`, used for the
+/// `OnlyShowIn`/`NotShowIn` checks.
+fn current_desktops() -> Vec<String> {
+    std::env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|desktop| !desktop.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Applies the Desktop Entry spec rules that decide whether an entry belongs
+/// in a launcher: `Type`, `Hidden`, `NoDisplay`, `OnlyShowIn`, `NotShowIn`
+/// and `TryExec`.
+fn should_show(section: &AttrSelector<&str>, current_desktops: &[String]) -> bool {
+    let is_true = |key: &str| section.attr(key) == Some("true");
+    let shown_in = |key: &str| {
+        section.attr(key).map(|desktops| {
+            desktops
+                .split(';')
+                .any(|desktop| current_desktops.iter().any(|current| current == desktop))
+        })
+    };
+
+    if section
+        .attr("Type")
+        .is_some_and(|kind| kind != "Application")
+    {
+        return false;
+    }
+    if is_true("Hidden") || is_true("NoDisplay") {
+        return false;
+    }
+    if shown_in("OnlyShowIn") == Some(false) || shown_in("NotShowIn") == Some(true) {
+        return false;
+    }
+    match section.attr("TryExec") {
+        Some(try_exec) => find_executable(try_exec).is_some(),
+        None => true,
+    }
+}
+
+/// Resolves `program` the way `execvp` would: absolute paths are checked
+/// directly, bare names are looked up on `PATH`.
+pub fn find_executable(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return is_executable(&path).then_some(path);
+    }
+
+    path_dirs()
+        .into_iter()
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
 }
 
 pub fn create_file_entry(path: String) -> Option<AppEntry> {
@@ -306,7 +873,7 @@ pub fn create_file_entry(path: String) -> Option<AppEntry> {
     let (icon_name, exec) = if metadata.is_dir() {
         ("folder", String::new())
     } else if metadata.permissions().mode() & 0o111 != 0 {
-        ("application-x-executable", format!("\"{}\"", path))
+        ("application-x-executable", exec::quote(&path))
     } else {
         let mime_type = match std::process::Command::new("file")
             .arg("--mime-type")
@@ -333,21 +900,149 @@ pub fn create_file_entry(path: String) -> Option<AppEntry> {
             _ => "text-x-generic",
         };
 
-        (
-            icon,
-            format!(
-                "xdg-mime query default {} | xargs -I {{}} sh -c 'which {{}} >/dev/null && {{}} \"{}\" || xdg-open \"{}\"'",
-                mime_type, path, path
-            ),
-        )
+        (icon, format!("xdg-open {}", exec::quote(&path)))
     };
 
     Some(AppEntry {
+        id: path.clone(),
         name,
         exec,
         icon_name: icon_name.to_string(),
         path,
-        launch_count: 0,
         entry_type: EntryType::File,
+        ..Default::default()
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use freedesktop_entry_parser::Entry;
+
+    const ENTRY: &str = "[Desktop Entry]\nType=Application\nName=App\nExec=app\n";
+
+    #[test]
+    fn desktop_file_id_joins_subdirectories_with_dashes() {
+        let root = Path::new("/usr/share/applications");
+        assert_eq!(
+            desktop_file_id(root, &root.join("firefox.desktop")).as_deref(),
+            Some("firefox.desktop")
+        );
+        assert_eq!(
+            desktop_file_id(root, &root.join("kde/org.kde.konsole.desktop")).as_deref(),
+            Some("kde-org.kde.konsole.desktop")
+        );
+    }
+
+    #[test]
+    fn desktop_file_id_rejects_other_files() {
+        let root = Path::new("/usr/share/applications");
+        assert_eq!(desktop_file_id(root, &root.join("mimeinfo.cache")), None);
+        assert_eq!(desktop_file_id(root, &root.join("kde")), None);
+        assert_eq!(
+            desktop_file_id(root, Path::new("/opt/app/app.desktop")),
+            None
+        );
+    }
+
+    #[test]
+    fn earlier_directories_shadow_later_ones() {
+        let home = TempDir::new();
+        let system = TempDir::new();
+        let shadowing = home.write("foo.desktop", ENTRY);
+        system.write("foo.desktop", ENTRY);
+        let only_system = system.write("bar.desktop", ENTRY);
+        let nested = system.write("kde/foo.desktop", ENTRY);
+
+        let mut files = desktop_files(&[home.path().to_path_buf(), system.path().to_path_buf()]);
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                (String::from("bar.desktop"), only_system),
+                (String::from("foo.desktop"), shadowing),
+                (String::from("kde-foo.desktop"), nested),
+            ]
+        );
+    }
+
+    fn shows(entry: &str, current_desktops: &[&str]) -> bool {
+        let entry = Entry::parse(format!("[Desktop Entry]\n{}", entry)).unwrap();
+        let current_desktops: Vec<_> = current_desktops.iter().map(|d| d.to_string()).collect();
+        should_show(&entry.section("Desktop Entry"), &current_desktops)
+    }
+
+    #[test]
+    fn should_show_applications_only() {
+        assert!(shows("Type=Application\n", &[]));
+        assert!(shows("Name=No type\n", &[]));
+        assert!(!shows("Type=Link\n", &[]));
+        assert!(!shows("Type=Directory\n", &[]));
+    }
+
+    #[test]
+    fn should_show_respects_hidden_and_no_display() {
+        assert!(!shows("Hidden=true\n", &[]));
+        assert!(!shows("NoDisplay=true\n", &[]));
+        assert!(shows("NoDisplay=false\n", &[]));
+    }
+
+    #[test]
+    fn should_show_respects_only_show_in_and_not_show_in() {
+        assert!(shows("OnlyShowIn=GNOME;Hyprland;\n", &["Hyprland"]));
+        assert!(!shows("OnlyShowIn=GNOME;KDE;\n", &["Hyprland"]));
+        assert!(!shows("OnlyShowIn=GNOME;\n", &[]));
+        assert!(!shows("NotShowIn=Hyprland;\n", &["sway", "Hyprland"]));
+        assert!(shows("NotShowIn=GNOME;\n", &["Hyprland"]));
+    }
+
+    #[test]
+    fn should_show_requires_try_exec_to_resolve() {
+        assert!(shows("TryExec=/bin/sh\n", &[]));
+        assert!(shows("TryExec=sh\n", &[]));
+        assert!(!shows("TryExec=/nonexistent/hyprlauncher-test\n", &[]));
+        assert!(!shows("TryExec=hyprlauncher-test-missing-binary\n", &[]));
+    }
+
+    #[test]
+    fn locale_variants_go_from_most_to_least_specific() {
+        assert_eq!(
+            locale_variants("sr_RS.UTF-8@latin"),
+            ["sr_RS@latin", "sr_RS", "sr@latin", "sr"]
+        );
+        assert_eq!(locale_variants("de_DE.UTF-8"), ["de_DE", "de"]);
+        assert_eq!(locale_variants("ca@valencia"), ["ca@valencia", "ca"]);
+        assert_eq!(locale_variants("fr"), ["fr"]);
+    }
+
+    #[test]
+    fn locale_variants_ignore_the_c_locale() {
+        assert!(locale_variants("").is_empty());
+        assert!(locale_variants("C").is_empty());
+        assert!(locale_variants("C.UTF-8").is_empty());
+        assert!(locale_variants("POSIX").is_empty());
+    }
+
+    #[test]
+    fn localized_prefers_the_most_specific_translation() {
+        let entry = Entry::parse(
+            "[Desktop Entry]\nName=Files\nName[de]=Dateien\nName[de_AT]=Dateien (AT)\n",
+        )
+        .unwrap();
+        let section = entry.section("Desktop Entry");
+        assert_eq!(
+            localized(&section, "Name", &locale_variants("de_AT.UTF-8")),
+            Some("Dateien (AT)")
+        );
+        assert_eq!(
+            localized(&section, "Name", &locale_variants("de_CH.UTF-8")),
+            Some("Dateien")
+        );
+        assert_eq!(
+            localized(&section, "Name", &locale_variants("fr_FR.UTF-8")),
+            Some("Files")
+        );
+        assert_eq!(localized(&section, "Comment", &locale_variants("de")), None);
+    }
+}
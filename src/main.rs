@@ -1,14 +1,65 @@
+mod activation;
 mod app;
+mod cache;
+mod cli;
 mod config;
+mod control;
+mod dmenu;
+mod exec;
+mod hyprland;
 mod launcher;
+mod notification;
+mod picker;
+mod plugin;
+mod provider;
+mod script;
 mod search;
+mod spawn;
+mod supervisor;
+mod systemd;
+#[cfg(test)]
+mod testing;
 mod ui;
+mod watcher;
 
 use app::App;
+use clap::Parser;
+use cli::{Cli, Command};
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     env_logger::init();
-    let app = App::new();
-    app.run();
+    let cli = Cli::parse();
+    if let Some(path) = cli.config {
+        config::set_config_file(path);
+    }
+    if let Some(path) = cli.css {
+        config::set_css_file(path);
+    }
+
+    if cli.picker.dmenu {
+        return dmenu::run(cli.picker);
+    }
+    if let Some(script) = cli.picker.script.clone() {
+        return script::run(script, cli.picker);
+    }
+
+    let mode = cli.mode.clone().unwrap_or_default();
+    let code = match cli.command {
+        Some(Command::Search { query, json }) => cli::search(&query, &mode, json).await,
+        Some(Command::Launch { id, terminal }) => cli::launch(&id, terminal).await,
+        None => {
+            let app = App::new(app::Options {
+                daemon: cli.daemon,
+                query: cli.query,
+                mode: cli.mode,
+            });
+            app.run();
+            ExitCode::SUCCESS
+        }
+    };
+    // Apps launched from here may outlive the launcher.
+    supervisor::hand_off();
+    code
 }
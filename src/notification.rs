@@ -0,0 +1,69 @@
+//! Desktop notifications through `org.freedesktop.Notifications`.
+
+use crate::launcher::AppEntry;
+use log::error;
+use std::collections::HashMap;
+use std::io;
+use zbus::zvariant::Value;
+use zbus::{Connection, proxy};
+
+/// How long notifications stay up, in milliseconds.
+const EXPIRE_TIMEOUT: i32 = 10_000;
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Shows an error notification with `summary` and `body`, which may use the
+/// markup subset of the spec, so callers escape untrusted text with
+/// [`escape`].
+pub async fn error(summary: &str, body: &str) -> zbus::Result<()> {
+    let connection = Connection::session().await?;
+    let notifications = NotificationsProxy::new(&connection).await?;
+    let hints = HashMap::from([("urgency", Value::from(2u8))]);
+    notifications
+        .notify(
+            "hyprlauncher",
+            0,
+            "dialog-error",
+            summary,
+            body,
+            &[],
+            hints,
+            EXPIRE_TIMEOUT,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Reports that launching `app` failed with `error`, through the log and a
+/// notification, since the launcher window may be gone by the time it is
+/// known.
+pub async fn launch_failed(app: &AppEntry, error: &io::Error) {
+    error!("Failed to launch {}: {}", app.id, error);
+    let summary = format!("Failed to launch {}", app.name);
+    if let Err(e) = self::error(&summary, &escape(&error.to_string())).await {
+        error!("Failed to show notification: {}", e);
+    }
+}
+
+/// Escapes `text` for use in a notification body.
+pub fn escape(text: &str) -> String {
+    glib::markup_escape_text(text).to_string()
+}
@@ -0,0 +1,409 @@
+//! A window for picking from a list of text rows, behind the dmenu and rofi
+//! script modes.
+
+use crate::config::Config;
+use crate::search;
+use crate::ui;
+use gtk4::gdk::{Key, ModifierType};
+use gtk4::gio::ApplicationFlags;
+use gtk4::glib::{self, clone};
+use gtk4::prelude::*;
+use gtk4::{Application, ApplicationWindow, Entry, Label, ListBox, ListBoxRow, ScrolledWindow};
+use gtk4::{Box as GtkBox, Orientation};
+use gtk4_layer_shell::{KeyboardMode, Layer, LayerShell};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+/// Rows listed at most, like the launcher's own results.
+const MAX_ROWS: usize = 100;
+
+pub struct Row {
+    pub text: String,
+    /// Icon name or path of an image file.
+    pub icon: Option<String>,
+    /// Data the caller attached to the row, not shown.
+    pub info: Option<String>,
+    /// Listed, but can not be chosen.
+    pub nonselectable: bool,
+}
+
+impl Row {
+    pub fn new(text: String) -> Self {
+        Self {
+            text,
+            icon: None,
+            info: None,
+            nonselectable: false,
+        }
+    }
+}
+
+/// Everything shown at once: the rows and the text around them.
+pub struct Page {
+    pub rows: Vec<Row>,
+    /// Shown in front of the search field.
+    pub prompt: Option<String>,
+    /// Pango markup shown above the rows.
+    pub message: Option<String>,
+    /// Whether typed text matching no row can be chosen.
+    pub allow_custom: bool,
+}
+
+impl Page {
+    pub fn new(rows: Vec<Row>) -> Self {
+        Self {
+            rows,
+            prompt: None,
+            message: None,
+            allow_custom: true,
+        }
+    }
+}
+
+pub enum Choice {
+    /// Positions of the chosen rows in the page.
+    Rows(Vec<usize>),
+    /// The typed text.
+    Text(String),
+}
+
+#[derive(Default)]
+pub struct Options {
+    pub ignore_case: bool,
+    /// Allow choosing several rows, marked with Tab.
+    pub multi_select: bool,
+    /// Mask the typed text.
+    pub password: bool,
+    /// Enter chooses the typed text, as Shift+Enter always does.
+    pub choose_text: bool,
+}
+
+struct State {
+    page: Page,
+    /// Text of every row, in page order, for matching.
+    texts: Vec<String>,
+    /// Page positions of the rows currently listed.
+    shown: Vec<usize>,
+    /// Page positions marked in multi-select mode.
+    marked: BTreeSet<usize>,
+}
+
+impl State {
+    fn new(page: Page) -> Self {
+        Self {
+            texts: page.rows.iter().map(|row| row.text.clone()).collect(),
+            page,
+            shown: Vec::new(),
+            marked: BTreeSet::new(),
+        }
+    }
+}
+
+/// Widgets of the picker window.
+#[derive(Clone)]
+struct View {
+    window: ApplicationWindow,
+    prompt: Label,
+    entry: Entry,
+    message: Label,
+    list: ListBox,
+}
+
+/// Shows `page` until the window is closed. `on_choice` gets every choice
+/// made and returns the page to show next, or `None` to close the window.
+pub fn run(
+    page: Page,
+    options: Options,
+    on_choice: impl FnMut(&Page, Choice) -> Option<Page> + 'static,
+) {
+    let state = Rc::new(RefCell::new(State::new(page)));
+    let options = Rc::new(options);
+    let on_choice = Rc::new(RefCell::new(on_choice));
+
+    // Every invocation is a picker of its own, never the resident launcher.
+    let app = Application::builder()
+        .application_id("hyprlauncher.picker")
+        .flags(ApplicationFlags::NON_UNIQUE)
+        .build();
+    app.connect_activate(move |app| {
+        let view = build_window(app, &options);
+        show_page(&view, &mut state.borrow_mut(), &options);
+        connect_signals(&view, &state, &options, &on_choice);
+        view.window.present();
+        view.entry.grab_focus();
+    });
+    let program: Vec<String> = std::env::args().take(1).collect();
+    app.run_with_args(&program);
+}
+
+fn build_window(app: &Application, options: &Options) -> View {
+    let config = Config::load();
+    let window = ApplicationWindow::builder()
+        .application(app)
+        .default_width(config.width)
+        .default_height(config.height)
+        .title("HyprLauncher")
+        .decorated(false)
+        .resizable(false)
+        .build();
+    window.init_layer_shell();
+    window.set_layer(Layer::Overlay);
+    window.set_keyboard_mode(KeyboardMode::Exclusive);
+
+    let prompt = Label::new(None);
+    prompt.add_css_class("prompt");
+    let entry = Entry::new();
+    entry.set_hexpand(true);
+    if options.password {
+        entry.set_visibility(false);
+        entry.set_input_purpose(gtk4::InputPurpose::Password);
+    }
+    let header = GtkBox::new(Orientation::Horizontal, 0);
+    header.append(&prompt);
+    header.append(&entry);
+
+    let message = Label::new(None);
+    message.set_halign(gtk4::Align::Start);
+    message.set_wrap(true);
+    message.add_css_class("message");
+
+    let list = ListBox::new();
+    list.set_selection_mode(gtk4::SelectionMode::Single);
+    let scrolled = ScrolledWindow::new();
+    scrolled.set_vexpand(true);
+    scrolled.set_child(Some(&list));
+
+    let main_box = GtkBox::new(Orientation::Vertical, 0);
+    main_box.append(&header);
+    main_box.append(&message);
+    main_box.append(&scrolled);
+    window.set_child(Some(&main_box));
+    ui::install_css(&window);
+
+    View {
+        window,
+        prompt,
+        entry,
+        message,
+        list,
+    }
+}
+
+fn connect_signals(
+    view: &View,
+    state: &Rc<RefCell<State>>,
+    options: &Rc<Options>,
+    on_choice: &Rc<RefCell<impl FnMut(&Page, Choice) -> Option<Page> + 'static>>,
+) {
+    view.entry.connect_changed(clone!(
+        #[strong]
+        view,
+        #[strong]
+        state,
+        #[strong]
+        options,
+        move |entry| {
+            update_rows(
+                &view.list,
+                &mut state.borrow_mut(),
+                &entry.text(),
+                options.ignore_case,
+            );
+        }
+    ));
+
+    view.list.connect_row_activated(clone!(
+        #[strong]
+        view,
+        #[strong]
+        state,
+        #[strong]
+        options,
+        #[strong]
+        on_choice,
+        move |_, row| {
+            let index = state.borrow().shown.get(row.index() as usize).copied();
+            if let Some(index) = index {
+                let choice = Choice::Rows(vec![index]);
+                apply_choice(&view, &state, &options, &on_choice, choice);
+            }
+        }
+    ));
+
+    // Runs in the capture phase so the entry never sees the keys used for
+    // navigating and choosing.
+    let key_controller = gtk4::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    key_controller.connect_key_pressed(clone!(
+        #[strong]
+        view,
+        #[strong]
+        state,
+        #[strong]
+        options,
+        #[strong]
+        on_choice,
+        move |_, key, _, modifiers| {
+            match key {
+                Key::Escape => view.window.close(),
+                Key::Up => select_offset(&view, -1),
+                Key::Down => select_offset(&view, 1),
+                Key::Tab if options.multi_select => {
+                    toggle_mark(&view.list, &mut state.borrow_mut());
+                    select_offset(&view, 1);
+                }
+                Key::Return | Key::KP_Enter => {
+                    let typed = modifiers.contains(ModifierType::SHIFT_MASK) || options.choose_text;
+                    let choice = choose(&view, &state.borrow(), typed);
+                    if let Some(choice) = choice {
+                        apply_choice(&view, &state, &options, &on_choice, choice);
+                    }
+                }
+                _ => return glib::Propagation::Proceed,
+            }
+            glib::Propagation::Stop
+        }
+    ));
+    view.window.add_controller(key_controller);
+}
+
+/// Hands `choice` to the caller and shows the page it returns, if any.
+fn apply_choice(
+    view: &View,
+    state: &Rc<RefCell<State>>,
+    options: &Options,
+    on_choice: &RefCell<impl FnMut(&Page, Choice) -> Option<Page>>,
+    choice: Choice,
+) {
+    let next = (on_choice.borrow_mut())(&state.borrow().page, choice);
+    match next {
+        Some(page) => {
+            *state.borrow_mut() = State::new(page);
+            // Emits `changed`, which lists the rows of the new page.
+            view.entry.set_text("");
+            show_page(view, &mut state.borrow_mut(), options);
+        }
+        None => view.window.close(),
+    }
+}
+
+fn show_page(view: &View, state: &mut State, options: &Options) {
+    view.prompt
+        .set_text(state.page.prompt.as_deref().unwrap_or(""));
+    view.prompt.set_visible(state.page.prompt.is_some());
+    view.message
+        .set_markup(state.page.message.as_deref().unwrap_or(""));
+    view.message.set_visible(state.page.message.is_some());
+    update_rows(&view.list, state, &view.entry.text(), options.ignore_case);
+}
+
+/// Lists the rows matching `query`, best first, and selects the first one
+/// that can be chosen.
+fn update_rows(list: &ListBox, state: &mut State, query: &str, ignore_case: bool) {
+    state.shown = search::match_lines(&state.texts, query, ignore_case);
+    state.shown.truncate(MAX_ROWS);
+
+    while let Some(child) = list.first_child() {
+        list.remove(&child);
+    }
+    for &index in &state.shown {
+        let row = &state.page.rows[index];
+        list.append(&create_row(row, state.marked.contains(&index)));
+    }
+    let first = state
+        .shown
+        .iter()
+        .position(|&index| !state.page.rows[index].nonselectable);
+    if let Some(row) = first.and_then(|position| list.row_at_index(position as i32)) {
+        list.select_row(Some(&row));
+    }
+}
+
+fn create_row(row: &Row, marked: bool) -> ListBoxRow {
+    let box_row = GtkBox::new(Orientation::Horizontal, 12);
+    box_row.set_margin_start(12);
+    box_row.set_margin_end(12);
+    box_row.set_margin_top(8);
+    box_row.set_margin_bottom(8);
+
+    if let Some(icon) = &row.icon {
+        let image = if icon.starts_with('/') {
+            gtk4::Image::from_file(icon)
+        } else {
+            gtk4::Image::from_icon_name(icon)
+        };
+        image.set_pixel_size(32);
+        box_row.append(&image);
+    }
+
+    let label = Label::new(Some(&row.text));
+    label.set_halign(gtk4::Align::Start);
+    label.add_css_class("app-name");
+    box_row.append(&label);
+
+    let list_row = ListBoxRow::new();
+    list_row.set_child(Some(&box_row));
+    if row.nonselectable {
+        list_row.set_selectable(false);
+        list_row.set_activatable(false);
+    }
+    if marked {
+        list_row.add_css_class("marked");
+    }
+    list_row
+}
+
+/// Moves the selection `offset` rows on, skipping rows that can not be
+/// chosen, while the entry keeps the focus.
+fn select_offset(view: &View, offset: i32) {
+    let Some(current) = view.list.selected_row() else {
+        return;
+    };
+    let mut index = current.index() + offset;
+    while let Some(row) = view.list.row_at_index(index) {
+        if row.is_selectable() {
+            view.list.select_row(Some(&row));
+            // Focusing the row scrolls it into view.
+            row.grab_focus();
+            view.entry.grab_focus_without_selecting();
+            return;
+        }
+        index += offset;
+    }
+}
+
+fn toggle_mark(list: &ListBox, state: &mut State) {
+    let Some(row) = list.selected_row() else {
+        return;
+    };
+    let Some(&index) = state.shown.get(row.index() as usize) else {
+        return;
+    };
+    if state.marked.remove(&index) {
+        row.remove_css_class("marked");
+    } else {
+        state.marked.insert(index);
+        row.add_css_class("marked");
+    }
+}
+
+/// The marked rows, the selected row or, with `typed` or when nothing
+/// matches, the typed text if the page allows it.
+fn choose(view: &View, state: &State, typed: bool) -> Option<Choice> {
+    let text = Choice::Text(view.entry.text().to_string());
+    if typed {
+        return state.page.allow_custom.then_some(text);
+    }
+    if !state.marked.is_empty() {
+        return Some(Choice::Rows(state.marked.iter().copied().collect()));
+    }
+    match view
+        .list
+        .selected_row()
+        .and_then(|row| state.shown.get(row.index() as usize))
+    {
+        Some(&index) => Some(Choice::Rows(vec![index])),
+        None => state.page.allow_custom.then_some(text),
+    }
+}
@@ -0,0 +1,678 @@
+//! External search providers: executables in the `plugins` folder of the
+//! configuration directory, speaking line-delimited JSON-RPC 2.0 on stdio.
+//!
+//! Requests sent to a plugin:
+//!
+//! - `initialize`, once after it started. The result declares the plugin,
+//!   e.g. `{"name": "calc", "prefix": "=", "capabilities": ["global"]}`.
+//!   With the `global` capability it also gets queries without its prefix,
+//!   with `activate` it handles activating its items.
+//! - `query`, with `{"query": "..."}`. The plugin sends any number of
+//!   `results` notifications, `{"id": <request id>, "items": [...]}`, then
+//!   answers the request. A `cancel` notification, `{"id": <request id>}`,
+//!   says the query was superseded or took too long.
+//! - `activate`, with `{"id": "<item id>", "action": "<action id>" | null}`.
+//!   Answering `{"query": "..."}` replaces the query and keeps the window
+//!   open; any other answer closes it.
+//!
+//! Items are `{"id", "title", "subtitle", "icon", "score", "exec",
+//! "terminal", "actions": [{"id", "title", "icon"}]}`, only `id` and `title`
+//! required. Without `activate`, activating an item runs its `exec` command
+//! line. Plugins keep running until their stdin is closed.
+
+use crate::config::Config;
+use crate::launcher::{AppAction, AppEntry, EntryType};
+use crate::provider::{Activation, Cancellation, SearchProvider, Sink};
+use crate::search::SearchResult;
+use log::{debug, error, warn};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+const PROTOCOL_VERSION: u32 = 1;
+
+const INITIALIZE_TIMEOUT: Duration = Duration::from_secs(2);
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+const ACTIVATE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often a query waiting for the plugin checks whether it was cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Capability of plugins that want queries without their prefix.
+const GLOBAL: &str = "global";
+/// Capability of plugins that handle activating their items.
+const ACTIVATE: &str = "activate";
+
+/// Result of `initialize`.
+#[derive(Deserialize)]
+struct Manifest {
+    name: String,
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(default)]
+    capabilities: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Item {
+    id: String,
+    title: String,
+    #[serde(default)]
+    subtitle: String,
+    #[serde(default)]
+    icon: Option<String>,
+    #[serde(default)]
+    score: i64,
+    #[serde(default)]
+    exec: String,
+    #[serde(default)]
+    terminal: bool,
+    #[serde(default)]
+    actions: Vec<ItemAction>,
+}
+
+#[derive(Deserialize)]
+struct ItemAction {
+    id: String,
+    title: String,
+    #[serde(default)]
+    icon: Option<String>,
+}
+
+/// A line from the plugin: the response to a request when `id` is set, a
+/// notification otherwise.
+#[derive(Deserialize)]
+struct Incoming {
+    id: Option<u64>,
+    method: Option<String>,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    result: Value,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ResultsParams {
+    id: u64,
+    items: Vec<Item>,
+}
+
+/// What the reader thread passes on to the request waiting for it.
+enum Reply {
+    Items(Vec<Item>),
+    Done(Result<Value, String>),
+}
+
+/// How waiting for the response to a request ended.
+enum Outcome {
+    Done(Result<Value, String>),
+    TimedOut,
+    /// The request was cancelled elsewhere or the plugin exited.
+    Dropped,
+}
+
+/// Requests waiting for their response, by request ID.
+type Pending = Arc<Mutex<HashMap<u64, Sender<Reply>>>>;
+
+/// One run of a plugin executable.
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    pending: Pending,
+    next_id: u64,
+}
+
+impl Process {
+    fn start(path: &Path) -> io::Result<Self> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(io::Error::other("no pipes to the plugin"));
+        };
+
+        let pending = Pending::default();
+        std::thread::spawn({
+            let pending = pending.clone();
+            let path = path.to_path_buf();
+            move || read_messages(&path, stdout, &pending)
+        });
+        Ok(Self {
+            child,
+            stdin,
+            pending,
+            next_id: 0,
+        })
+    }
+
+    fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> io::Result<(u64, Receiver<Reply>)> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let (tx, rx) = mpsc::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        let message = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        if let Err(e) = self.write(&message) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        Ok((id, rx))
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        self.write(&json!({"jsonrpc": "2.0", "method": method, "params": params}))
+    }
+
+    fn write(&mut self, message: &Value) -> io::Result<()> {
+        writeln!(self.stdin, "{}", message)?;
+        self.stdin.flush()
+    }
+
+    /// Stops waiting for request `id` and tells the plugin to stop working
+    /// on it.
+    fn cancel(&mut self, id: u64) {
+        if self.pending.lock().unwrap().remove(&id).is_some() {
+            let _ = self.notify("cancel", json!({ "id": id }));
+        }
+    }
+}
+
+/// Hands every response and `results` notification from `stdout` to the
+/// request it belongs to, until the plugin exits.
+fn read_messages(path: &Path, stdout: impl Read, pending: &Pending) {
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        let message: Incoming = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Invalid message from plugin {:?}: {}", path, e);
+                continue;
+            }
+        };
+
+        match (message.id, message.method.as_deref()) {
+            (Some(id), None) => {
+                let result = match message.error {
+                    Some(error) => Err(error.message),
+                    None => Ok(message.result),
+                };
+                if let Some(tx) = pending.lock().unwrap().remove(&id) {
+                    let _ = tx.send(Reply::Done(result));
+                }
+            }
+            (None, Some("results")) => {
+                match serde_json::from_value::<ResultsParams>(message.params) {
+                    Ok(results) => {
+                        if let Some(tx) = pending.lock().unwrap().get(&results.id) {
+                            let _ = tx.send(Reply::Items(results.items));
+                        }
+                    }
+                    Err(e) => warn!("Invalid results from plugin {:?}: {}", path, e),
+                }
+            }
+            (_, method) => debug!("Ignoring {:?} from plugin {:?}", method, path),
+        }
+    }
+    // Dropping the senders wakes up every request still waiting.
+    pending.lock().unwrap().clear();
+}
+
+/// Waits up to `timeout` for the response to a request, handing the items
+/// streamed before it to `on_items`. Stops early, as if out of time, when
+/// `on_items` returns false or `cancellation` is set.
+fn wait(
+    replies: &Receiver<Reply>,
+    timeout: Duration,
+    cancellation: &Cancellation,
+    mut on_items: impl FnMut(Vec<Item>) -> bool,
+) -> Outcome {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if cancellation.is_cancelled() {
+            return Outcome::TimedOut;
+        }
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match replies.recv_timeout(timeout.min(CANCEL_POLL_INTERVAL)) {
+            Ok(Reply::Items(items)) => {
+                if !on_items(items) {
+                    return Outcome::TimedOut;
+                }
+            }
+            Ok(Reply::Done(result)) => return Outcome::Done(result),
+            Err(RecvTimeoutError::Timeout) if Instant::now() < deadline => {}
+            Err(RecvTimeoutError::Timeout) => return Outcome::TimedOut,
+            Err(RecvTimeoutError::Disconnected) => return Outcome::Dropped,
+        }
+    }
+}
+
+/// A plugin executable, started again when it exits.
+pub struct Plugin {
+    path: PathBuf,
+    manifest: Manifest,
+    process: Mutex<Process>,
+}
+
+impl Plugin {
+    fn start(path: &Path) -> io::Result<Self> {
+        let (process, manifest) = Self::spawn(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            manifest,
+            process: Mutex::new(process),
+        })
+    }
+
+    /// Starts the executable and has it declare itself.
+    fn spawn(path: &Path) -> io::Result<(Process, Manifest)> {
+        let mut process = Process::start(path)?;
+        let manifest = process
+            .request("initialize", json!({ "version": PROTOCOL_VERSION }))
+            .and_then(|(_, replies)| {
+                match wait(
+                    &replies,
+                    INITIALIZE_TIMEOUT,
+                    &Cancellation::default(),
+                    |_| true,
+                ) {
+                    Outcome::Done(Ok(result)) => {
+                        serde_json::from_value(result).map_err(io::Error::other)
+                    }
+                    Outcome::Done(Err(e)) => Err(io::Error::other(e)),
+                    Outcome::TimedOut => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "no answer to initialize",
+                    )),
+                    Outcome::Dropped => Err(io::Error::other("exited while initializing")),
+                }
+            });
+        match manifest {
+            Ok(manifest) => Ok((process, manifest)),
+            Err(e) => {
+                let _ = process.child.kill();
+                let _ = process.child.wait();
+                Err(e)
+            }
+        }
+    }
+
+    fn has(&self, capability: &str) -> bool {
+        self.manifest.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// The running process, started again if it exited.
+    fn process(&self) -> io::Result<MutexGuard<'_, Process>> {
+        let mut process = self.process.lock().unwrap();
+        if !process.is_running() {
+            warn!("Plugin {} exited, starting it again", self.manifest.name);
+            *process = Self::spawn(&self.path)?.0;
+        }
+        Ok(process)
+    }
+
+    fn query(&self, query: &str) -> io::Result<(u64, Receiver<Reply>)> {
+        self.process()?.request("query", json!({ "query": query }))
+    }
+
+    /// Tells the plugin to stop working on query `id` unless it finished,
+    /// leaving any other query running.
+    fn finish_query(&self, id: u64, outcome: &Outcome) {
+        if let Outcome::TimedOut = outcome {
+            self.process.lock().unwrap().cancel(id);
+        }
+    }
+
+    /// Sends `query` and hands the results streamed back to `on_results`
+    /// until the plugin is done, `timeout` passes, the search is cancelled or
+    /// `on_results` returns false.
+    fn run_query(
+        &self,
+        query: &str,
+        exclusive: bool,
+        timeout: Duration,
+        cancellation: &Cancellation,
+        mut on_results: impl FnMut(Vec<SearchResult>) -> bool,
+    ) {
+        if !exclusive && !self.has(GLOBAL) {
+            return;
+        }
+        let (id, replies) = match self.query(query) {
+            Ok(request) => request,
+            Err(e) => {
+                error!("Failed to query plugin {}: {}", self.manifest.name, e);
+                return;
+            }
+        };
+
+        let outcome = wait(&replies, timeout, cancellation, |items| {
+            on_results(items.into_iter().map(|item| self.result(item)).collect())
+        });
+        match &outcome {
+            Outcome::Done(Err(e)) => warn!("Plugin {} failed a query: {}", self.manifest.name, e),
+            Outcome::TimedOut if cancellation.is_cancelled() => {}
+            Outcome::TimedOut => debug!("Plugin {} ran out of time", self.manifest.name),
+            Outcome::Done(Ok(_)) | Outcome::Dropped => {}
+        }
+        self.finish_query(id, &outcome);
+    }
+
+    fn result(&self, item: Item) -> SearchResult {
+        let app = AppEntry {
+            id: item.id,
+            name: item.title,
+            exec: item.exec,
+            icon_name: item
+                .icon
+                .unwrap_or_else(|| String::from("application-x-executable")),
+            path: item.subtitle,
+            entry_type: EntryType::Provider(self.manifest.name.clone()),
+            actions: item
+                .actions
+                .into_iter()
+                .map(|action| AppAction {
+                    id: action.id,
+                    name: action.title,
+                    exec: String::new(),
+                    icon_name: action.icon,
+                })
+                .collect(),
+            terminal: item.terminal,
+            ..Default::default()
+        };
+        SearchResult {
+            app,
+            score: item.score,
+        }
+    }
+}
+
+impl SearchProvider for Plugin {
+    fn name(&self) -> &str {
+        &self.manifest.name
+    }
+
+    fn default_prefix(&self) -> Option<&str> {
+        self.manifest.prefix.as_deref()
+    }
+
+    fn search(
+        &self,
+        query: &str,
+        exclusive: bool,
+        cancellation: &Cancellation,
+    ) -> Vec<SearchResult> {
+        let mut results = Vec::new();
+        self.run_query(query, exclusive, QUERY_TIMEOUT, cancellation, |batch| {
+            results.extend(batch);
+            true
+        });
+        results
+    }
+
+    fn search_streaming(&self, query: &str, exclusive: bool, sink: &Sink) {
+        let cancellation = sink.cancellation();
+        // Without a budget, still give up on a plugin that hangs.
+        let timeout = sink.remaining().unwrap_or(QUERY_TIMEOUT);
+        self.run_query(query, exclusive, timeout, cancellation, |batch| {
+            sink.send(batch)
+        });
+    }
+
+    fn budget(&self) -> Option<Duration> {
+        Some(QUERY_TIMEOUT)
+    }
+
+    fn activate(&self, entry: &AppEntry) -> Activation {
+        if !self.has(ACTIVATE) {
+            return if entry.exec.is_empty() {
+                Activation::Close
+            } else {
+                Activation::Launch
+            };
+        }
+
+        let (item, action) = match entry.entry_type {
+            EntryType::ProviderAction(_) => (entry.app_id(), entry.action_id()),
+            _ => (entry.id.as_str(), None),
+        };
+        let outcome = self
+            .process()
+            .and_then(|mut process| {
+                process.request("activate", json!({ "id": item, "action": action }))
+            })
+            .map(|(id, replies)| {
+                let outcome = wait(&replies, ACTIVATE_TIMEOUT, &Cancellation::default(), |_| {
+                    true
+                });
+                if let Outcome::TimedOut = outcome {
+                    self.process.lock().unwrap().cancel(id);
+                }
+                outcome
+            });
+        match outcome {
+            Ok(Outcome::Done(Ok(result))) => match result.get("query").and_then(Value::as_str) {
+                Some(query) => Activation::Query(query.to_string()),
+                None => Activation::Close,
+            },
+            Ok(Outcome::Done(Err(e))) => {
+                warn!(
+                    "Plugin {} failed to activate {}: {}",
+                    self.manifest.name, item, e
+                );
+                Activation::Close
+            }
+            Ok(Outcome::TimedOut | Outcome::Dropped) => {
+                warn!(
+                    "Plugin {} did not answer activating {}",
+                    self.manifest.name, item
+                );
+                Activation::Close
+            }
+            Err(e) => {
+                error!("Failed to reach plugin {}: {}", self.manifest.name, e);
+                Activation::Close
+            }
+        }
+    }
+}
+
+/// Starts every executable in the plugins folder, in file name order.
+pub fn load() -> Vec<Plugin> {
+    let Ok(entries) = std::fs::read_dir(Config::plugins_dir()) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+        .collect();
+    paths.sort();
+
+    paths
+        .iter()
+        .filter_map(|path| match Plugin::start(path) {
+            Ok(plugin) => Some(plugin),
+            Err(e) => {
+                error!("Failed to start plugin {:?}: {}", path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    /// Answers `initialize`, streams one item and an invalid line for every
+    /// `query` after a moment, and turns `activate` into a new query. Request IDs are
+    /// counted rather than parsed, as they are handed out in order.
+    const SCRIPT: &str = r#"#!/bin/sh
+n=0
+while read -r line; do
+    case "$line" in
+        *'"method":"cancel"'*) continue ;;
+    esac
+    n=$((n + 1))
+    case "$line" in
+        *'"method":"initialize"'*)
+            echo '{"jsonrpc":"2.0","id":'$n',"result":{"name":"stub","prefix":"?","capabilities":["activate"]}}' ;;
+        *'"method":"query"'*)
+            sleep 0.05
+            echo '{"jsonrpc":"2.0","method":"results","params":{"id":'$n',"items":[{"id":"a","title":"A","score":5,"exec":"true"}]}}'
+            echo 'not json'
+            echo '{"jsonrpc":"2.0","id":'$n',"result":null}' ;;
+        *'"method":"activate"'*)
+            echo '{"jsonrpc":"2.0","id":'$n',"result":{"query":"sub/"}}' ;;
+    esac
+done
+"#;
+
+    fn start_plugin(dir: &TempDir, script: &str) -> io::Result<Plugin> {
+        let path = dir.write("plugin", script);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        Plugin::start(&path)
+    }
+
+    fn expect(pending: &Pending, id: u64) -> Receiver<Reply> {
+        let (tx, rx) = mpsc::channel();
+        pending.lock().unwrap().insert(id, tx);
+        rx
+    }
+
+    #[test]
+    fn read_messages_routes_replies_to_their_request() {
+        let pending = Pending::default();
+        let first = expect(&pending, 1);
+        let second = expect(&pending, 2);
+        let input = concat!(
+            "garbage\n",
+            r#"{"jsonrpc":"2.0","method":"results","params":{"id":1,"items":[{"id":"a","title":"A"}]}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","method":"results","params":{"id":1}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","method":"log","params":{}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":2,"error":{"code":1,"message":"nope"}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":1,"result":{"ok":true}}"#,
+            "\n",
+        );
+        read_messages(Path::new("stub"), input.as_bytes(), &pending);
+
+        match first.try_recv() {
+            Ok(Reply::Items(items)) => {
+                assert_eq!(items.len(), 1);
+                assert_eq!(items[0].id, "a");
+            }
+            _ => panic!("expected the streamed items"),
+        }
+        assert!(matches!(
+            first.try_recv(),
+            Ok(Reply::Done(Ok(result))) if result == json!({"ok": true})
+        ));
+        assert!(matches!(
+            second.try_recv(),
+            Ok(Reply::Done(Err(message))) if message == "nope"
+        ));
+        assert!(pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_messages_drops_requests_left_at_eof() {
+        let pending = Pending::default();
+        let replies = expect(&pending, 1);
+        read_messages(Path::new("stub"), &b""[..], &pending);
+        let outcome = wait(&replies, ACTIVATE_TIMEOUT, &Cancellation::default(), |_| {
+            true
+        });
+        assert!(matches!(outcome, Outcome::Dropped));
+    }
+
+    #[test]
+    fn wait_stops_early() {
+        let (tx, rx) = mpsc::channel();
+        let outcome = wait(
+            &rx,
+            Duration::from_millis(10),
+            &Cancellation::default(),
+            |_| true,
+        );
+        assert!(matches!(outcome, Outcome::TimedOut));
+
+        let cancellation = Cancellation::default();
+        cancellation.cancel();
+        let outcome = wait(&rx, ACTIVATE_TIMEOUT, &cancellation, |_| true);
+        assert!(matches!(outcome, Outcome::TimedOut));
+
+        tx.send(Reply::Items(Vec::new())).unwrap();
+        tx.send(Reply::Done(Ok(Value::Null))).unwrap();
+        let outcome = wait(&rx, ACTIVATE_TIMEOUT, &Cancellation::default(), |_| false);
+        assert!(matches!(outcome, Outcome::TimedOut));
+    }
+
+    #[test]
+    fn plugin_answers_queries_and_activation() {
+        let dir = TempDir::new();
+        let plugin = start_plugin(&dir, SCRIPT).expect("start plugin");
+        assert_eq!(plugin.name(), "stub");
+        assert_eq!(plugin.default_prefix(), Some("?"));
+
+        let cancellation = Cancellation::default();
+        assert!(plugin.search("x", false, &cancellation).is_empty());
+        let results = plugin.search("x", true, &cancellation);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].score, 5);
+        let app = &results[0].app;
+        assert_eq!((app.id.as_str(), app.name.as_str()), ("a", "A"));
+        assert!(matches!(&app.entry_type, EntryType::Provider(name) if name == "stub"));
+
+        assert!(matches!(plugin.activate(app), Activation::Query(query) if query == "sub/"));
+        // Still in step after the activation.
+        assert_eq!(plugin.search("y", true, &cancellation).len(), 1);
+    }
+
+    #[test]
+    fn concurrent_queries_do_not_cancel_each_other() {
+        let dir = TempDir::new();
+        let plugin = start_plugin(&dir, SCRIPT).expect("start plugin");
+        let cancellation = Cancellation::default();
+        let counts: Vec<usize> = std::thread::scope(|scope| {
+            let searches: Vec<_> = ["x", "y"]
+                .into_iter()
+                .map(|query| scope.spawn(|| plugin.search(query, true, &cancellation).len()))
+                .collect();
+            searches.into_iter().map(|s| s.join().unwrap()).collect()
+        });
+        assert_eq!(counts, [1, 1]);
+    }
+
+    #[test]
+    fn plugin_exiting_before_its_answer_fails_to_start() {
+        let dir = TempDir::new();
+        let error = start_plugin(&dir, "#!/bin/sh\nread -r line\n")
+            .err()
+            .expect("plugin should not start");
+        assert_eq!(error.to_string(), "exited while initializing");
+    }
+}
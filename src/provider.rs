@@ -0,0 +1,653 @@
+//! Search providers, the sources results come from, and the routing of a
+//! query to them.
+
+use crate::config::Config;
+use crate::launcher::{AppEntry, EntryType};
+use crate::plugin;
+use crate::search::{self, AppProvider, FileProvider, RunProvider, SearchMode, SearchResult};
+use log::warn;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Results listed at most.
+pub const MAX_RESULTS: usize = 100;
+
+/// How long a provider may take to send its results, unless it sets its own
+/// budget.
+const DEFAULT_BUDGET: Duration = Duration::from_secs(1);
+
+/// What activating a result does.
+pub enum Activation {
+    /// Start the entry through the launch backend and close the window.
+    Launch,
+    /// Replace the query and keep the window open, e.g. to enter a folder.
+    Query(String),
+    /// The provider handled it; close the window.
+    Close,
+}
+
+pub trait SearchProvider: Send + Sync {
+    /// Identifies the provider in `config.toml` and as a search mode.
+    fn name(&self) -> &str;
+
+    /// Prefix that sends a query to this provider alone, unless
+    /// `provider_prefixes` sets another one.
+    fn default_prefix(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether `query` belongs to this provider alone, even without its
+    /// prefix.
+    fn claims(&self, _query: &str) -> bool {
+        false
+    }
+
+    /// Results for `query`, which has its prefix removed. `exclusive` is set
+    /// when no other provider is asked. Runs on a blocking thread; long loops
+    /// should stop early once `cancellation` is set.
+    fn search(
+        &self,
+        query: &str,
+        exclusive: bool,
+        cancellation: &Cancellation,
+    ) -> Vec<SearchResult>;
+
+    /// Like [`SearchProvider::search`], sending the results to `sink` as
+    /// they are found. Providers that find results over time override it.
+    fn search_streaming(&self, query: &str, exclusive: bool, sink: &Sink) {
+        sink.send(self.search(query, exclusive, &sink.cancellation));
+    }
+
+    /// How long the provider may take in the window, `None` for as long as
+    /// it needs. The batch sent once it ran out is the last one shown.
+    fn budget(&self) -> Option<Duration> {
+        Some(DEFAULT_BUDGET)
+    }
+
+    /// Decides what activating `entry`, one of this provider's results, does.
+    fn activate(&self, _entry: &AppEntry) -> Activation {
+        Activation::Launch
+    }
+}
+
+struct Registered {
+    provider: Box<dyn SearchProvider>,
+    prefix: Option<String>,
+}
+
+/// The providers enabled in the configuration, in order.
+struct Registry {
+    providers: Vec<Registered>,
+}
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(|| Registry::new(&Config::load()));
+
+impl Registry {
+    fn new(config: &Config) -> Self {
+        let builtins: Vec<Box<dyn SearchProvider>> = vec![
+            Box::new(AppProvider),
+            Box::new(RunProvider),
+            Box::new(FileProvider),
+        ];
+        let plugins = plugin::load()
+            .into_iter()
+            .map(|plugin| Box::new(plugin) as Box<dyn SearchProvider>)
+            .collect();
+        Self::with_providers(config, builtins, plugins)
+    }
+
+    /// Enables `builtins` and `plugins` as `config` says. Plugins named like
+    /// a provider before them are left out, as results are routed back to
+    /// their provider by name.
+    fn with_providers(
+        config: &Config,
+        mut builtins: Vec<Box<dyn SearchProvider>>,
+        mut plugins: Vec<Box<dyn SearchProvider>>,
+    ) -> Self {
+        let mut names: HashSet<String> = builtins.iter().map(|p| p.name().to_string()).collect();
+        plugins.retain(|plugin| {
+            let unique = names.insert(plugin.name().to_string());
+            if !unique {
+                warn!(
+                    "Ignoring plugin {:?}, a search provider of that name exists already",
+                    plugin.name()
+                );
+            }
+            unique
+        });
+        let mut enabled: Vec<_> = config
+            .providers
+            .iter()
+            .filter_map(|name| {
+                let provider = take(&mut builtins, name).or_else(|| take(&mut plugins, name));
+                if provider.is_none() {
+                    warn!("Unknown or duplicate search provider {:?}", name);
+                }
+                provider
+            })
+            .collect();
+        // Plugins are opted out of by removing them from the plugins folder.
+        enabled.append(&mut plugins);
+
+        let providers = enabled
+            .into_iter()
+            .map(|provider| {
+                let prefix = match config.provider_prefixes.get(provider.name()) {
+                    Some(prefix) => Some(prefix.clone()).filter(|prefix| !prefix.is_empty()),
+                    None => provider.default_prefix().map(str::to_string),
+                };
+                Registered { provider, prefix }
+            })
+            .collect();
+        Self { providers }
+    }
+
+    fn get(&self, name: &str) -> Option<&Registered> {
+        self.providers.iter().find(|r| r.provider.name() == name)
+    }
+
+    /// The providers `query` goes to in `mode`, each with the query it gets
+    /// and whether it is the only one: the provider of `mode`, the provider
+    /// whose prefix the query starts with, the provider claiming it, or else
+    /// every provider.
+    fn route(&self, query: &str, mode: &SearchMode) -> Vec<(&dyn SearchProvider, String, bool)> {
+        if let SearchMode::Provider(name) = mode {
+            let Some(registered) = self.get(name) else {
+                return Vec::new();
+            };
+            let query = registered
+                .prefix
+                .as_deref()
+                .and_then(|prefix| query.strip_prefix(prefix))
+                .unwrap_or(query);
+            return vec![(registered.provider.as_ref(), query.to_string(), true)];
+        }
+
+        // The longest prefix wins, so e.g. `:` and `::` can both be used.
+        let prefixed = self
+            .providers
+            .iter()
+            .filter_map(|r| Some((r, query.strip_prefix(r.prefix.as_deref()?)?)))
+            .max_by_key(|(r, _)| r.prefix.as_ref().map_or(0, String::len));
+        if let Some((registered, rest)) = prefixed {
+            return vec![(registered.provider.as_ref(), rest.to_string(), true)];
+        }
+
+        if let Some(registered) = self.providers.iter().find(|r| r.provider.claims(query)) {
+            return vec![(registered.provider.as_ref(), query.to_string(), true)];
+        }
+
+        self.providers
+            .iter()
+            .map(|r| (r.provider.as_ref(), query.to_string(), false))
+            .collect()
+    }
+
+    fn activate(&self, entry: &AppEntry) -> Activation {
+        let owner = match &entry.entry_type {
+            EntryType::Application | EntryType::Action => "apps",
+            EntryType::Binary => "run",
+            EntryType::File => "files",
+            EntryType::Provider(name) | EntryType::ProviderAction(name) => name,
+        };
+        self.get(owner)
+            .map_or(Activation::Launch, |r| r.provider.activate(entry))
+    }
+}
+
+/// Removes the provider called `name` from `providers`.
+fn take(
+    providers: &mut Vec<Box<dyn SearchProvider>>,
+    name: &str,
+) -> Option<Box<dyn SearchProvider>> {
+    let index = providers.iter().position(|p| p.name() == name)?;
+    Some(providers.remove(index))
+}
+
+/// Sorts `results` best first, keeping the order of equal ones, and drops
+/// duplicates and the ones past [`MAX_RESULTS`].
+fn rank(mut results: Vec<SearchResult>) -> Vec<SearchResult> {
+    results.sort_by_key(|result| std::cmp::Reverse(result.score));
+    search::dedup(&mut results);
+    results.truncate(MAX_RESULTS);
+    results
+}
+
+/// Set once a search is superseded, telling its providers to stop.
+#[derive(Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Hands out the generations of searches, cancelling the previous search
+/// whenever a new one starts.
+#[derive(Default)]
+pub struct Searches {
+    generation: u64,
+    cancellation: Cancellation,
+}
+
+impl Searches {
+    /// Cancels the running search and returns the generation and
+    /// cancellation token of the next one.
+    pub fn start(&mut self) -> (u64, Cancellation) {
+        self.cancel();
+        self.generation += 1;
+        self.cancellation = Cancellation::default();
+        (self.generation, self.cancellation.clone())
+    }
+
+    /// Cancels the running search.
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    /// Whether `generation` is the latest search started.
+    pub fn is_current(&self, generation: u64) -> bool {
+        generation == self.generation
+    }
+}
+
+/// Results a provider sent, tagged with the generation of their search.
+pub struct Batch {
+    pub generation: u64,
+    pub results: Vec<SearchResult>,
+}
+
+/// Where a provider sends its results while it searches.
+pub struct Sink {
+    tx: mpsc::UnboundedSender<Batch>,
+    generation: u64,
+    cancellation: Cancellation,
+    provider: String,
+    /// When the provider's budget runs out, if it has one.
+    deadline: Option<Instant>,
+    /// Set once a batch was sent past the deadline.
+    out_of_time: Cell<bool>,
+}
+
+impl Sink {
+    /// A sink for `provider`'s results of search `generation`, sent on
+    /// `tx` until `deadline`, if there is one.
+    pub fn new(
+        tx: mpsc::UnboundedSender<Batch>,
+        generation: u64,
+        cancellation: Cancellation,
+        provider: &str,
+        deadline: Option<Instant>,
+    ) -> Self {
+        Self {
+            tx,
+            generation,
+            cancellation,
+            provider: provider.to_string(),
+            deadline,
+            out_of_time: Cell::new(false),
+        }
+    }
+
+    /// Passes `results` on, ranked. Returns false once the search was
+    /// cancelled or dropped or the provider's budget has run out, after
+    /// which it should stop. The batch that finds the budget used up is
+    /// still passed on, so a provider sending everything at once loses
+    /// nothing; later ones are dropped.
+    pub fn send(&self, results: Vec<SearchResult>) -> bool {
+        if self.cancellation.is_cancelled() {
+            return false;
+        }
+        if self.out_of_time.get() {
+            warn!(
+                "Dropping {} results of {}, which ran out of time",
+                results.len(),
+                self.provider
+            );
+            return false;
+        }
+        let late = self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline);
+        if late {
+            warn!(
+                "{} ran out of time, ignoring its further results",
+                self.provider
+            );
+            self.out_of_time.set(true);
+        }
+        let batch = Batch {
+            generation: self.generation,
+            results: rank(results),
+        };
+        self.tx.send(batch).is_ok() && !late
+    }
+
+    pub fn cancellation(&self) -> &Cancellation {
+        &self.cancellation
+    }
+
+    /// What is left of the provider's budget, `None` without one.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// Searches every provider `query` goes to in `mode` at once, as search
+/// `generation`. Each sends its ranked results on the returned channel as it
+/// finds them; the channel closes once all are done, out of time or
+/// cancelled through `cancellation`.
+pub fn search(
+    query: &str,
+    mode: &SearchMode,
+    generation: u64,
+    cancellation: Cancellation,
+) -> mpsc::UnboundedReceiver<Batch> {
+    start_search(query, mode, generation, cancellation, true)
+}
+
+/// Starts [`search`], holding the providers to their budgets if `budgeted`.
+fn start_search(
+    query: &str,
+    mode: &SearchMode,
+    generation: u64,
+    cancellation: Cancellation,
+    budgeted: bool,
+) -> mpsc::UnboundedReceiver<Batch> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let query = query.to_string();
+    let mode = mode.clone();
+    // Routing starts the plugins on first use, so keep it off the caller's
+    // thread too.
+    tokio::task::spawn_blocking(move || {
+        for (provider, query, exclusive) in REGISTRY.route(&query, &mode) {
+            if cancellation.is_cancelled() {
+                return;
+            }
+            let sink = Sink::new(
+                tx.clone(),
+                generation,
+                cancellation.clone(),
+                provider.name(),
+                provider
+                    .budget()
+                    .filter(|_| budgeted)
+                    .map(|budget| Instant::now() + budget),
+            );
+            tokio::task::spawn_blocking(move || {
+                provider.search_streaming(&query, exclusive, &sink)
+            });
+        }
+    });
+    rx
+}
+
+/// Waits for every result of [`search`] and ranks them together. Nobody is
+/// typing ahead here, so the providers take as long as they need.
+pub async fn search_ranked(query: &str, mode: &SearchMode) -> Vec<SearchResult> {
+    let mut batches = start_search(query, mode, 0, Cancellation::default(), false);
+    let mut results = Vec::new();
+    while let Some(batch) = batches.recv().await {
+        results.extend(batch.results);
+    }
+    rank(results)
+}
+
+/// Starts the providers, plugins included, in the background so the first
+/// search does not wait for them.
+pub fn init() {
+    tokio::task::spawn_blocking(|| LazyLock::force(&REGISTRY));
+}
+
+/// What activating `entry` does, as decided by the provider it came from.
+pub async fn activate(entry: &AppEntry) -> Activation {
+    let entry = entry.clone();
+    // A plugin may take a while to answer, so keep it off the caller's thread.
+    tokio::task::spawn_blocking(move || REGISTRY.activate(&entry))
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Failed to activate an entry: {}", e);
+            Activation::Close
+        })
+}
+
+/// Whether `mode` names no provider that is enabled.
+pub fn is_unknown(mode: &SearchMode) -> bool {
+    match mode {
+        SearchMode::All => false,
+        SearchMode::Provider(name) => REGISTRY.get(name).is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Stub {
+        name: &'static str,
+        prefix: Option<&'static str>,
+        claimed: Option<&'static str>,
+    }
+
+    impl SearchProvider for Stub {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn default_prefix(&self) -> Option<&str> {
+            self.prefix
+        }
+
+        fn claims(&self, query: &str) -> bool {
+            self.claimed.is_some_and(|start| query.starts_with(start))
+        }
+
+        fn search(&self, _: &str, _: bool, _: &Cancellation) -> Vec<SearchResult> {
+            Vec::new()
+        }
+    }
+
+    fn stub(
+        name: &'static str,
+        prefix: Option<&'static str>,
+        claimed: Option<&'static str>,
+    ) -> Box<dyn SearchProvider> {
+        Box::new(Stub {
+            name,
+            prefix,
+            claimed,
+        })
+    }
+
+    /// A registry with `apps`, `run` (prefix `>`) and `files` (claiming
+    /// `/`) built in, and the plugins `calc` (prefix `=`) and `emoji`
+    /// (prefix `:`).
+    fn registry(config: &Config) -> Registry {
+        Registry::with_providers(
+            config,
+            vec![
+                stub("apps", None, None),
+                stub("run", Some(">"), None),
+                stub("files", None, Some("/")),
+            ],
+            vec![
+                stub("calc", Some("="), None),
+                stub("emoji", Some(":"), None),
+            ],
+        )
+    }
+
+    fn route(registry: &Registry, query: &str, mode: &SearchMode) -> Vec<(String, String, bool)> {
+        registry
+            .route(query, mode)
+            .into_iter()
+            .map(|(provider, query, exclusive)| (provider.name().to_string(), query, exclusive))
+            .collect()
+    }
+
+    fn routed(name: &str, query: &str, exclusive: bool) -> (String, String, bool) {
+        (name.to_string(), query.to_string(), exclusive)
+    }
+
+    #[test]
+    fn plain_queries_go_to_every_provider_in_order() {
+        let registry = registry(&Config::default());
+        assert_eq!(
+            route(&registry, "fire", &SearchMode::All),
+            [
+                routed("apps", "fire", false),
+                routed("run", "fire", false),
+                routed("files", "fire", false),
+                routed("calc", "fire", false),
+                routed("emoji", "fire", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefixes_route_to_one_provider_without_the_prefix() {
+        let registry = registry(&Config::default());
+        assert_eq!(
+            route(&registry, ">htop", &SearchMode::All),
+            [routed("run", "htop", true)]
+        );
+        assert_eq!(
+            route(&registry, "=1+1", &SearchMode::All),
+            [routed("calc", "1+1", true)]
+        );
+    }
+
+    #[test]
+    fn the_longest_prefix_wins() {
+        let config = Config {
+            provider_prefixes: HashMap::from([(String::from("calc"), String::from("::"))]),
+            ..Default::default()
+        };
+        let registry = registry(&config);
+        assert_eq!(
+            route(&registry, "::pi", &SearchMode::All),
+            [routed("calc", "pi", true)]
+        );
+        assert_eq!(
+            route(&registry, ":smile", &SearchMode::All),
+            [routed("emoji", "smile", true)]
+        );
+    }
+
+    #[test]
+    fn configured_prefixes_replace_or_disable_defaults() {
+        let config = Config {
+            provider_prefixes: HashMap::from([
+                (String::from("run"), String::from("!")),
+                (String::from("calc"), String::new()),
+            ]),
+            ..Default::default()
+        };
+        let registry = registry(&config);
+        assert_eq!(
+            route(&registry, "!htop", &SearchMode::All),
+            [routed("run", "htop", true)]
+        );
+        assert_eq!(route(&registry, ">htop", &SearchMode::All).len(), 5);
+        assert_eq!(route(&registry, "=1", &SearchMode::All).len(), 5);
+    }
+
+    #[test]
+    fn claimed_queries_go_to_the_claiming_provider_whole() {
+        let registry = registry(&Config::default());
+        assert_eq!(
+            route(&registry, "/etc/", &SearchMode::All),
+            [routed("files", "/etc/", true)]
+        );
+    }
+
+    #[test]
+    fn modes_route_to_their_provider_alone() {
+        let registry = registry(&Config::default());
+        let run = SearchMode::Provider(String::from("run"));
+        assert_eq!(
+            route(&registry, "htop", &run),
+            [routed("run", "htop", true)]
+        );
+        // A typed prefix is dropped, other providers' prefixes are not.
+        assert_eq!(
+            route(&registry, ">htop", &run),
+            [routed("run", "htop", true)]
+        );
+        assert_eq!(route(&registry, "=1", &run), [routed("run", "=1", true)]);
+        let unknown = SearchMode::Provider(String::from("bogus"));
+        assert!(route(&registry, "htop", &unknown).is_empty());
+    }
+
+    #[test]
+    fn providers_follow_the_configured_order() {
+        let config = Config {
+            providers: ["calc", "apps", "bogus", "apps"].map(String::from).to_vec(),
+            ..Default::default()
+        };
+        let registry = registry(&config);
+        let names: Vec<_> = route(&registry, "x", &SearchMode::All)
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect();
+        // Built-ins left out stay off; plugins left out come last.
+        assert_eq!(names, ["calc", "apps", "emoji"]);
+        assert!(registry.get("run").is_none());
+    }
+
+    #[test]
+    fn plugins_cannot_take_a_used_name() {
+        let registry = Registry::with_providers(
+            &Config::default(),
+            vec![stub("apps", None, None)],
+            vec![
+                stub("apps", Some("="), None),
+                stub("calc", Some("="), None),
+                stub("calc", Some(":"), None),
+            ],
+        );
+        let providers: Vec<_> = registry
+            .providers
+            .iter()
+            .map(|r| (r.provider.name(), r.prefix.as_deref()))
+            .collect();
+        assert_eq!(providers, [("apps", None), ("calc", Some("="))]);
+    }
+
+    #[test]
+    fn sink_drops_results_once_out_of_time() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let late = Sink::new(
+            tx.clone(),
+            1,
+            Cancellation::default(),
+            "late",
+            Some(Instant::now()),
+        );
+        assert!(!late.send(Vec::new()));
+        assert!(!late.send(Vec::new()));
+        assert_eq!(late.remaining(), Some(Duration::ZERO));
+        assert_eq!(rx.try_recv().map(|batch| batch.generation).ok(), Some(1));
+        assert!(rx.try_recv().is_err());
+
+        let unbudgeted = Sink::new(tx, 2, Cancellation::default(), "slow", None);
+        assert!(unbudgeted.send(Vec::new()));
+        assert_eq!(unbudgeted.remaining(), None);
+        assert_eq!(rx.try_recv().map(|batch| batch.generation).ok(), Some(2));
+        assert!(rx.try_recv().is_err());
+
+        unbudgeted.cancellation().cancel();
+        assert!(!unbudgeted.send(Vec::new()));
+    }
+}
@@ -0,0 +1,208 @@
+//! rofi script mode: the script prints the rows to show and is run again
+//! with the chosen one, until it prints no rows.
+//!
+//! Rows are `text\0key\x1fvalue\x1f...` with the `icon`, `info` and
+//! `nonselectable` keys, and lines of the form `\0key\x1fvalue` set the
+//! `prompt`, `message`, `data` and `no-custom` options of the page.
+
+use crate::cli::PickerArgs;
+use crate::picker::{self, Choice, Page, Row};
+use std::cell::Cell;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+use std::rc::Rc;
+
+/// `ROFI_RETV` values: why the script is being run.
+const RETV_INITIAL: u8 = 0;
+const RETV_SELECTED: u8 = 1;
+const RETV_CUSTOM: u8 = 2;
+
+/// What one run of the script printed.
+struct Output {
+    page: Page,
+    /// Passed back to the next run as `ROFI_DATA`.
+    data: Option<String>,
+}
+
+/// Shows the rows of `script` until it prints none. Exits with failure
+/// when the picker was cancelled or the script could not run.
+pub fn run(script: PathBuf, args: PickerArgs) -> ExitCode {
+    // Used when the script sets no prompt, as rofi does with the mode name.
+    let default_prompt = args.prompt.clone().or_else(|| {
+        script
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+    });
+
+    let output = match invoke(&script, RETV_INITIAL, None, None, None) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Failed to run {:?}: {}", script, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if output.page.rows.is_empty() {
+        return ExitCode::SUCCESS;
+    }
+
+    let finished = Rc::new(Cell::new(false));
+    let mut data = output.data;
+    let mut page = output.page;
+    page.prompt = page.prompt.or_else(|| default_prompt.clone());
+    picker::run(page, args.picker_options(), {
+        let finished = finished.clone();
+        move |page, choice| {
+            let runs: Vec<(u8, String, Option<String>)> = match choice {
+                Choice::Rows(indices) => indices
+                    .into_iter()
+                    .map(|index| &page.rows[index])
+                    .map(|row| (RETV_SELECTED, row.text.clone(), row.info.clone()))
+                    .collect(),
+                Choice::Text(text) => vec![(RETV_CUSTOM, text, None)],
+            };
+
+            let mut next = None;
+            for (retv, arg, info) in runs {
+                match invoke(&script, retv, Some(&arg), info.as_deref(), data.as_deref()) {
+                    Ok(output) => {
+                        data = output.data;
+                        next = Some(output.page);
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to run {:?}: {}", script, e);
+                        return None;
+                    }
+                }
+            }
+
+            let mut next = next.filter(|page| !page.rows.is_empty());
+            match &mut next {
+                Some(page) => page.prompt = page.prompt.take().or_else(|| default_prompt.clone()),
+                None => finished.set(true),
+            }
+            next
+        }
+    });
+
+    if finished.get() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Runs `script` once, with the chosen row as its argument.
+fn invoke(
+    script: &Path,
+    retv: u8,
+    arg: Option<&str>,
+    info: Option<&str>,
+    data: Option<&str>,
+) -> io::Result<Output> {
+    let mut command = Command::new(script);
+    command
+        .args(arg)
+        .env("ROFI_RETV", retv.to_string())
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit());
+    if let Some(info) = info {
+        command.env("ROFI_INFO", info);
+    }
+    if let Some(data) = data {
+        command.env("ROFI_DATA", data);
+    }
+
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("exited with {}", output.status)));
+    }
+    Ok(parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse(stdout: &str) -> Output {
+    let mut page = Page::new(Vec::new());
+    let mut data = None;
+
+    for line in stdout.lines() {
+        if let Some(option) = line.strip_prefix('\0') {
+            let (key, value) = option.split_once('\x1f').unwrap_or((option, ""));
+            match key {
+                "prompt" => page.prompt = Some(value.to_string()),
+                "message" => page.message = Some(value.to_string()),
+                "data" => data = Some(value.to_string()),
+                "no-custom" => page.allow_custom = value != "true",
+                _ => {}
+            }
+            continue;
+        }
+
+        let (text, options) = line.split_once('\0').unwrap_or((line, ""));
+        let mut row = Row::new(text.to_string());
+        let mut fields = options.split('\x1f');
+        while let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+            match key {
+                "icon" => row.icon = Some(value.to_string()),
+                "info" => row.info = Some(value.to_string()),
+                "nonselectable" => row.nonselectable = value == "true",
+                _ => {}
+            }
+        }
+        page.rows.push(row);
+    }
+
+    Output { page, data }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_rows_and_their_options() {
+        let output = parse(
+            "plain\nwith icon\0icon\x1ffirefox\x1finfo\x1fid-1\nheader\0nonselectable\x1ftrue\n",
+        );
+        let rows = &output.page.rows;
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].text, "plain");
+        assert_eq!(rows[0].icon, None);
+        assert_eq!(rows[1].text, "with icon");
+        assert_eq!(rows[1].icon.as_deref(), Some("firefox"));
+        assert_eq!(rows[1].info.as_deref(), Some("id-1"));
+        assert!(!rows[1].nonselectable);
+        assert!(rows[2].nonselectable);
+    }
+
+    #[test]
+    fn parse_reads_page_options() {
+        let output = parse(
+            "\0prompt\x1fPick\n\0message\x1f<b>Hi</b>\n\0data\x1fstate 2\n\0no-custom\x1ftrue\nrow\n",
+        );
+        assert_eq!(output.page.prompt.as_deref(), Some("Pick"));
+        assert_eq!(output.page.message.as_deref(), Some("<b>Hi</b>"));
+        assert_eq!(output.data.as_deref(), Some("state 2"));
+        assert!(!output.page.allow_custom);
+        assert_eq!(output.page.rows.len(), 1);
+    }
+
+    #[test]
+    fn parse_ignores_unknown_and_incomplete_options() {
+        let output = parse("\0unknown\x1fx\n\0prompt\nrow\0meta\x1fwords\x1ficon\nlast\n");
+        assert_eq!(output.page.prompt.as_deref(), Some(""));
+        assert!(output.page.allow_custom);
+        assert_eq!(output.data, None);
+        let rows = &output.page.rows;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].text, "row");
+        assert_eq!(rows[0].icon, None);
+        assert_eq!(rows[1].text, "last");
+    }
+
+    #[test]
+    fn parse_of_nothing_is_an_empty_page() {
+        let output = parse("");
+        assert!(output.page.rows.is_empty());
+        assert!(output.page.allow_custom);
+    }
+}
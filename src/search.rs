@@ -1,166 +1,440 @@
-use std::collections::HashMap;
+//! Matching and ranking, and the built-in search providers.
 
-use crate::launcher::{self, APP_CACHE, AppEntry};
+use crate::launcher::{self, APP_CACHE, AppEntry, EntryType};
+use crate::provider::{Activation, Cancellation, SearchProvider, Sink};
 use fuzzy_matcher::FuzzyMatcher;
 use fuzzy_matcher::skim::SkimMatcherV2;
 use rayon::prelude::*;
-use tokio::sync::{RwLock, oneshot};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
+#[derive(Serialize)]
 pub struct SearchResult {
     pub app: AppEntry,
     pub score: i64,
 }
 
-pub async fn search_applications(query: &str) -> Vec<SearchResult> {
-    let (tx, rx) = oneshot::channel();
-    let query = query.to_lowercase();
-
-    tokio::task::spawn_blocking(move || {
-        let cache = APP_CACHE.get_or_init(|| RwLock::new(HashMap::new()));
-        let results = if query.starts_with('~') || query.starts_with('$') || query.starts_with('/')
-        {
-            let expanded_path =
-                shellexpand::full(&query).unwrap_or(std::borrow::Cow::Borrowed(&query));
-
-            if expanded_path.ends_with('/') {
-                if let Ok(entries) = std::fs::read_dir(expanded_path.as_ref()) {
-                    let mut matches: Vec<_> = entries
-                        .filter_map(|entry| entry.ok())
-                        .filter_map(|entry| {
-                            launcher::create_file_entry(entry.path().to_string_lossy().to_string())
-                                .map(|entry| SearchResult {
-                                    app: entry,
-                                    score: 1000,
-                                })
-                        })
-                        .collect();
-                    matches.sort_by(|a, b| {
-                        match (a.app.icon_name == "folder", b.app.icon_name == "folder") {
-                            (true, false) => std::cmp::Ordering::Less,
-                            (false, true) => std::cmp::Ordering::Greater,
-                            _ => a.app.name.cmp(&b.app.name),
-                        }
-                    });
-                    matches.truncate(100);
-                    matches
-                } else {
-                    Vec::new()
-                }
-            } else {
-                let path = std::path::Path::new(expanded_path.as_ref());
-                if let Some(parent) = path.parent() {
-                    if let Ok(entries) = std::fs::read_dir(parent) {
-                        let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-
-                        let mut matches: Vec<_> = entries
-                            .filter_map(|entry| entry.ok())
-                            .filter_map(|entry| {
-                                let name = entry.file_name();
-                                let name_str = name.to_string_lossy();
-                                if name_str.contains(file_name) {
-                                    launcher::create_file_entry(
-                                        entry.path().to_string_lossy().to_string(),
-                                    )
-                                    .map(|entry| {
-                                        SearchResult {
-                                            app: entry,
-                                            score: 1000,
-                                        }
-                                    })
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect();
-                        matches.sort_by(|a, b| b.score.cmp(&a.score));
-                        matches.truncate(100);
-                        matches
-                    } else {
-                        Vec::new()
-                    }
-                } else {
-                    Vec::new()
+/// Which providers a query goes to.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum SearchMode {
+    /// Every provider, unless the query starts with a provider's prefix or
+    /// a provider claims it.
+    #[default]
+    All,
+    /// Only the provider with this name, e.g. `run`.
+    Provider(String),
+}
+
+impl FromStr for SearchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err(String::from("expected all or a provider name")),
+            "all" => Ok(Self::All),
+            name => Ok(Self::Provider(name.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for SearchMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::All => f.write_str("all"),
+            Self::Provider(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Applications from desktop files and their actions.
+pub struct AppProvider;
+
+impl SearchProvider for AppProvider {
+    fn name(&self) -> &str {
+        "apps"
+    }
+
+    fn search(
+        &self,
+        query: &str,
+        _exclusive: bool,
+        cancellation: &Cancellation,
+    ) -> Vec<SearchResult> {
+        let query = query.to_lowercase();
+        let cache = APP_CACHE.blocking_read();
+        let apps = cache
+            .values()
+            .filter(|app| matches!(app.entry_type, EntryType::Application));
+        if query.is_empty() {
+            return by_popularity(apps);
+        }
+
+        let matcher = SkimMatcherV2::default().smart_case();
+        let mut results = Vec::new();
+        for app in apps {
+            if cancellation.is_cancelled() {
+                return Vec::new();
+            }
+            if let Some(score) = match_score(&matcher, app, &query) {
+                results.push(SearchResult {
+                    app: app.clone(),
+                    score: score + popularity_score(app),
+                });
+            }
+            for action in &app.actions {
+                let entry = app.action_entry(action);
+                if let Some(score) = matcher.fuzzy_match(&entry.name.to_lowercase(), &query) {
+                    let score = score + popularity_score(&entry);
+                    results.push(SearchResult { app: entry, score });
                 }
             }
-        } else if query.is_empty() {
-            let mut results: Vec<_> = cache
-                .blocking_read()
-                .values()
-                .par_bridge()
-                .filter(|app| app.path.contains("/applications/") && app.path.ends_with(".desktop"))
-                .map(|app| {
-                    let heat_score = if app.launch_count > 0 {
-                        (app.launch_count as i64 * 100) + 2000
-                    } else {
-                        0
-                    };
-
-                    let icon_score = if app.icon_name == "application-x-executable" {
-                        0
-                    } else {
-                        1000
-                    };
-
-                    SearchResult {
-                        app: app.clone(),
-                        score: heat_score + icon_score,
-                    }
+        }
+        results
+    }
+}
+
+/// Executables in `PATH`.
+pub struct RunProvider;
+
+impl SearchProvider for RunProvider {
+    fn name(&self) -> &str {
+        "run"
+    }
+
+    fn default_prefix(&self) -> Option<&str> {
+        Some(">")
+    }
+
+    fn search(
+        &self,
+        query: &str,
+        exclusive: bool,
+        cancellation: &Cancellation,
+    ) -> Vec<SearchResult> {
+        let query = query.to_lowercase();
+        // Binaries would crowd out the applications on an empty query.
+        if query.is_empty() && !exclusive {
+            return Vec::new();
+        }
+        let cache = APP_CACHE.blocking_read();
+        let binaries = cache
+            .values()
+            .filter(|app| matches!(app.entry_type, EntryType::Binary));
+        if query.is_empty() {
+            return by_popularity(binaries);
+        }
+
+        let matcher = SkimMatcherV2::default().smart_case();
+        binaries
+            .take_while(|_| !cancellation.is_cancelled())
+            .filter_map(|app| {
+                let score = match_score(&matcher, app, &query)?;
+                Some(SearchResult {
+                    app: app.clone(),
+                    score: score + popularity_score(app),
                 })
-                .collect();
+            })
+            .collect()
+    }
+}
 
-            results.par_sort_unstable_by(|a, b| b.score.cmp(&a.score));
-            results.dedup_by(|a, b| a.app.name == b.app.name);
-            results.truncate(100);
-            results
-        } else {
-            let matcher = SkimMatcherV2::default().smart_case();
-            let mut seen_names = std::collections::HashSet::new();
-            let mut seen_execs = std::collections::HashSet::new();
-            let mut results: Vec<SearchResult> = Vec::new();
-
-            for app in cache.blocking_read().values().collect::<Vec<_>>().iter() {
-                if let Some(score) = matcher.fuzzy_match(&app.name.to_lowercase(), &query) {
-                    let name_lower = app.name.to_lowercase();
-                    let exec_name = app
-                        .path
-                        .split('/')
-                        .last()
-                        .unwrap_or("")
-                        .split('.')
-                        .next()
-                        .unwrap_or("")
-                        .to_lowercase();
-
-                    if !seen_names.contains(&name_lower) && !seen_execs.contains(&exec_name) {
-                        seen_names.insert(name_lower);
-                        seen_execs.insert(exec_name);
-
-                        let heat_score = if app.launch_count > 0 {
-                            (app.launch_count as i64 * 100) + 2000
-                        } else {
-                            0
-                        };
-
-                        let icon_score = if app.icon_name == "application-x-executable" {
-                            0
-                        } else {
-                            1000
-                        };
-
-                        results.push(SearchResult {
-                            app: (*app).clone(),
-                            score: score + heat_score + icon_score,
-                        });
-                    }
-                }
+/// Score of files. Folders get one more, so they stay above the files of
+/// every chunk [`FileProvider`] streams.
+const FILE_SCORE: i64 = 1000;
+
+/// Entries [`FileProvider`] sends at a time.
+const FILE_CHUNK_SIZE: usize = 64;
+
+/// Entries of the folder the path `query` points into whose name contains
+/// its last component, in directory order. A trailing slash lists the
+/// folder itself.
+fn file_matches<'a>(
+    query: &str,
+    cancellation: &'a Cancellation,
+) -> impl Iterator<Item = SearchResult> + use<'a> {
+    let query = if is_path_query(query) {
+        query.to_string()
+    } else {
+        format!("~/{}", query)
+    };
+    let expanded_path = shellexpand::full(&query).unwrap_or(Cow::Borrowed(&query));
+    let path = Path::new(expanded_path.as_ref());
+
+    let (dir, file_name) = if expanded_path.ends_with('/') {
+        (Some(path), String::new())
+    } else {
+        (
+            path.parent(),
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("")
+                .to_string(),
+        )
+    };
+    dir.and_then(|dir| std::fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .take_while(|_| !cancellation.is_cancelled())
+        .filter_map(|entry| entry.ok())
+        .filter(move |entry| entry.file_name().to_string_lossy().contains(&file_name))
+        .filter_map(|entry| launcher::create_file_entry(entry.path().to_string_lossy().to_string()))
+        .map(|entry| SearchResult {
+            score: if entry.icon_name == "folder" {
+                FILE_SCORE + 1
+            } else {
+                FILE_SCORE
+            },
+            app: entry,
+        })
+}
+
+/// Sorts folders first, then by name.
+fn sort_files(results: &mut [SearchResult]) {
+    results.sort_by(
+        |a, b| match (a.app.icon_name == "folder", b.app.icon_name == "folder") {
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            _ => a.app.name.cmp(&b.app.name),
+        },
+    );
+}
+
+/// Files and folders. Claims queries starting with `/`, `~` or `$`; other
+/// queries are taken relative to the home directory.
+pub struct FileProvider;
+
+impl SearchProvider for FileProvider {
+    fn name(&self) -> &str {
+        "files"
+    }
+
+    fn claims(&self, query: &str) -> bool {
+        is_path_query(query)
+    }
+
+    fn search(
+        &self,
+        query: &str,
+        exclusive: bool,
+        cancellation: &Cancellation,
+    ) -> Vec<SearchResult> {
+        if !exclusive {
+            return Vec::new();
+        }
+        let mut matches: Vec<_> = file_matches(query, cancellation).collect();
+        sort_files(&mut matches);
+        matches
+    }
+
+    /// Sends the entries in chunks, so the first ones of a slow or large
+    /// folder show up right away.
+    fn search_streaming(&self, query: &str, exclusive: bool, sink: &Sink) {
+        if !exclusive {
+            return;
+        }
+        let mut matches = file_matches(query, sink.cancellation());
+        loop {
+            let mut chunk: Vec<_> = matches.by_ref().take(FILE_CHUNK_SIZE).collect();
+            if chunk.is_empty() {
+                return;
+            }
+            sort_files(&mut chunk);
+            if !sink.send(chunk) {
+                return;
             }
+        }
+    }
 
-            results.par_sort_unstable_by(|a, b| b.score.cmp(&a.score));
-            results.truncate(100);
-            results
+    /// Listing a folder is asked for by typing its path, so all of it is
+    /// shown however long it takes.
+    fn budget(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Opens folders in the launcher instead of a file manager.
+    fn activate(&self, entry: &AppEntry) -> Activation {
+        if entry.icon_name != "folder" {
+            return Activation::Launch;
+        }
+        if entry.path.ends_with('/') {
+            Activation::Query(entry.path.clone())
+        } else {
+            Activation::Query(format!("{}/", entry.path))
+        }
+    }
+}
+
+/// Drops applications and binaries sharing a name or executable with a
+/// better ranked one, e.g. `firefox` next to Firefox.
+pub fn dedup(results: &mut Vec<SearchResult>) {
+    let mut seen_names = HashSet::new();
+    let mut seen_execs = HashSet::new();
+    results.retain(|result| {
+        let Some((name, exec_name)) = dedup_key(&result.app) else {
+            return true;
         };
-        let _ = tx.send(results);
+        let new = !seen_names.contains(&name) && !seen_execs.contains(&exec_name);
+        seen_names.insert(name);
+        seen_execs.insert(exec_name);
+        new
     });
+}
+
+/// Whether `a` and `b` are the same program, as [`dedup`] decides it.
+pub fn is_duplicate(a: &AppEntry, b: &AppEntry) -> bool {
+    match (dedup_key(a), dedup_key(b)) {
+        (Some((a_name, a_exec)), Some((b_name, b_exec))) => a_name == b_name || a_exec == b_exec,
+        _ => false,
+    }
+}
+
+/// Lowercased name and executable name of applications and binaries.
+fn dedup_key(app: &AppEntry) -> Option<(String, String)> {
+    if !matches!(app.entry_type, EntryType::Application | EntryType::Binary) {
+        return None;
+    }
+    let exec_name = app
+        .path
+        .split('/')
+        .next_back()
+        .unwrap_or("")
+        .split('.')
+        .next()
+        .unwrap_or("")
+        .to_lowercase();
+    Some((app.name.to_lowercase(), exec_name))
+}
+
+/// Every one of `apps`, most popular first, for an empty query.
+fn by_popularity<'a>(apps: impl Iterator<Item = &'a AppEntry>) -> Vec<SearchResult> {
+    apps.map(|app| SearchResult {
+        app: app.clone(),
+        score: popularity_score(app),
+    })
+    .collect()
+}
+
+/// Fuzzy-matches `query` against plain text `lines`, returning the indices
+/// of the matching lines, best first. An empty query keeps every line in
+/// its original order.
+pub fn match_lines(lines: &[String], query: &str, ignore_case: bool) -> Vec<usize> {
+    if query.is_empty() {
+        return (0..lines.len()).collect();
+    }
+
+    let matcher = if ignore_case {
+        SkimMatcherV2::default().ignore_case()
+    } else {
+        SkimMatcherV2::default().respect_case()
+    };
+    let mut matches: Vec<(usize, i64)> = lines
+        .par_iter()
+        .enumerate()
+        .filter_map(|(index, line)| Some((index, matcher.fuzzy_match(line, query)?)))
+        .collect();
+    // Stable, so equally good matches keep their input order.
+    matches.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+    matches.into_iter().map(|(index, _)| index).collect()
+}
+
+fn is_path_query(query: &str) -> bool {
+    query.starts_with('~') || query.starts_with('$') || query.starts_with('/')
+}
+
+/// Scores `app` against the lowercased `query`. `GenericName` and `Keywords`
+/// matches count for half a name match and `Comment` matches, which must
+/// contain the query verbatim, for a quarter.
+fn match_score(matcher: &SkimMatcherV2, app: &AppEntry, query: &str) -> Option<i64> {
+    let name = matcher.fuzzy_match(&app.name.to_lowercase(), query);
+    let generic_name = app
+        .generic_name
+        .as_ref()
+        .and_then(|generic_name| matcher.fuzzy_match(&generic_name.to_lowercase(), query))
+        .map(|score| score / 2);
+    let keywords = app
+        .keywords
+        .iter()
+        .filter_map(|keyword| matcher.fuzzy_match(&keyword.to_lowercase(), query))
+        .max()
+        .map(|score| score / 2);
+    let comment = app
+        .comment
+        .as_ref()
+        .map(|comment| comment.to_lowercase())
+        .filter(|comment| comment.contains(query))
+        .and_then(|comment| matcher.fuzzy_match(&comment, query))
+        .map(|score| score / 4);
+
+    [name, generic_name, keywords, comment]
+        .into_iter()
+        .flatten()
+        .max()
+}
+
+/// Ranks frequently launched entries and entries with a real icon higher.
+fn popularity_score(app: &AppEntry) -> i64 {
+    let heat_score = if app.launch_count > 0 {
+        (app.launch_count as i64 * 100) + 2000
+    } else {
+        0
+    };
+
+    let icon_score = if app.icon_name == "application-x-executable" {
+        0
+    } else {
+        1000
+    };
+
+    heat_score + icon_score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn file_provider_streams_chunks_with_folders_first() {
+        let dir = TempDir::new();
+        for i in 0..FILE_CHUNK_SIZE {
+            std::fs::create_dir(dir.path().join(format!("folder{:02}", i))).unwrap();
+        }
+        dir.write("file", "");
+        let query = format!("{}/", dir.path().display());
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let sink = Sink::new(tx, 0, Cancellation::default(), "files", None);
+        FileProvider.search_streaming(&query, true, &sink);
+        drop(sink);
+        let mut batches = Vec::new();
+        while let Ok(batch) = rx.try_recv() {
+            batches.push(batch.results);
+        }
+        assert_eq!(batches.len(), 2);
+        let results: Vec<_> = batches.into_iter().flatten().collect();
+        assert_eq!(results.len(), FILE_CHUNK_SIZE + 1);
+        let file = results.iter().find(|r| r.app.name == "file").unwrap();
+        assert!(
+            results
+                .iter()
+                .filter(|r| r.app.name != "file")
+                .all(|r| r.app.icon_name == "folder" && r.score > file.score)
+        );
 
-    rx.await.unwrap_or_default()
+        let listed = FileProvider.search(&query, true, &Cancellation::default());
+        assert_eq!(listed.len(), FILE_CHUNK_SIZE + 1);
+        assert_eq!(listed[0].app.name, "folder00");
+        assert_eq!(listed[FILE_CHUNK_SIZE].app.name, "file");
+        assert!(
+            FileProvider
+                .search(&query, false, &Cancellation::default())
+                .is_empty()
+        );
+    }
 }
@@ -0,0 +1,409 @@
+//! Starting launched entries, as child processes or over D-Bus.
+
+use crate::activation;
+use crate::config::{Config, LaunchBackend};
+use crate::exec::{self, FieldCodes};
+use crate::hyprland;
+use crate::launcher::{self, AppEntry};
+use crate::supervisor;
+use crate::systemd;
+use log::warn;
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Terminal emulators tried when none is configured, with the arguments that
+/// make them run the command appended after them.
+const KNOWN_TERMINALS: &[(&str, &[&str])] = &[
+    ("kitty", &[]),
+    ("foot", &[]),
+    ("alacritty", &["-e"]),
+    ("wezterm", &["start", "--"]),
+    ("ghostty", &["-e"]),
+    ("konsole", &["-e"]),
+    ("gnome-terminal", &["--"]),
+    ("xterm", &["-e"]),
+];
+
+/// How long a spawned child is watched for an early failure.
+const GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// Choices made when activating an entry, on top of what the entry asks for.
+#[derive(Clone, Default)]
+pub struct LaunchOptions {
+    /// Run the entry inside a terminal even without `Terminal=true`.
+    pub in_terminal: bool,
+    /// xdg-activation token handed to the launched application.
+    pub activation_token: Option<String>,
+    /// Hyprland workspace to open the application on.
+    pub workspace: Option<u32>,
+}
+
+/// Starts `app`. Entries with `DBusActivatable=true` are activated over D-Bus
+/// and fall back to their `Exec` line when that fails.
+///
+/// Spawned children are watched for [`GRACE_PERIOD`], so an `Exec` that
+/// cannot run or exits with an error status is reported as an error carrying
+/// the start of its stderr.
+pub async fn launch(app: &AppEntry, files: &[String], options: &LaunchOptions) -> io::Result<()> {
+    let config = Config::load();
+    let rules = hyprland_rules(app, options, &config);
+
+    if app.dbus_activatable && !options.in_terminal && rules.is_empty() {
+        let token = options.activation_token.as_deref();
+        match activation::activate(app, files, token).await {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("D-Bus activation of {} failed, using Exec: {}", app.id, e),
+        }
+    }
+
+    if config.launch_backend == LaunchBackend::Hyprland || options.workspace.is_some() {
+        return hyprland::exec(&rules, &shell_command(app, files, options)?);
+    }
+
+    let child = command(app, files, options)?
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if config.launch_backend == LaunchBackend::Systemd
+        && let Err(e) = systemd::move_to_scope(app, child.id()).await
+    {
+        warn!("Failed to move {} into a systemd scope: {}", app.id, e);
+    }
+    supervisor::supervise(child, GRACE_PERIOD).await
+}
+
+/// Prepares the process for `app`: argv, working directory and environment.
+///
+/// The child starts in a session of its own with default signal handling,
+/// and only sees the variables of the launcher's environment that the
+/// `env_allow`/`env_deny` settings let through.
+pub fn command(app: &AppEntry, files: &[String], options: &LaunchOptions) -> io::Result<Command> {
+    let config = Config::load();
+    let argv = command_line(app, files, options)?;
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]).stdin(Stdio::null());
+
+    if let Some(dir) = working_dir(app) {
+        command.current_dir(dir);
+    }
+    command
+        .env_clear()
+        .envs(inherited_env(&config))
+        .envs(launch_env(app, options, &config));
+    // SAFETY: only async-signal-safe calls are made between fork and exec.
+    unsafe {
+        command.pre_exec(detach);
+    }
+    Ok(command)
+}
+
+/// Runs in the forked child: leaves the launcher's session, so the child
+/// survives it and gets no signals meant for it, and undoes any signal
+/// dispositions and mask it inherited.
+pub fn detach() -> io::Result<()> {
+    // SAFETY: plain libc calls on memory owned by this frame.
+    unsafe {
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        for signal in 1..libc::SIGRTMAX() {
+            if signal != libc::SIGKILL && signal != libc::SIGSTOP {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+        }
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigprocmask(libc::SIG_SETMASK, &set, std::ptr::null_mut());
+    }
+    Ok(())
+}
+
+/// Variables of the launcher's own environment that launched apps keep.
+fn inherited_env(config: &Config) -> Vec<(OsString, OsString)> {
+    std::env::vars_os()
+        .filter(|(key, _)| is_inherited(config, &key.to_string_lossy()))
+        .collect()
+}
+
+/// Whether the launcher's variable `key` is passed on to launched apps.
+fn is_inherited(config: &Config, key: &str) -> bool {
+    // Tokens the launcher itself was started with are used up.
+    if key == "XDG_ACTIVATION_TOKEN" || key == "DESKTOP_STARTUP_ID" {
+        return false;
+    }
+    let allowed = config.env_allow.is_empty()
+        || config
+            .env_allow
+            .iter()
+            .any(|pattern| env_matches(pattern, key));
+    allowed
+        && !config
+            .env_deny
+            .iter()
+            .any(|pattern| env_matches(pattern, key))
+}
+
+/// Matches a variable name against an `env_allow`/`env_deny` entry.
+fn env_matches(pattern: &str, key: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => key.starts_with(prefix),
+        None => key == pattern,
+    }
+}
+
+/// Renders what [`command`] would run as a `/bin/sh` command line, for
+/// launchers that only accept a string.
+pub fn shell_command(
+    app: &AppEntry,
+    files: &[String],
+    options: &LaunchOptions,
+) -> io::Result<String> {
+    let config = Config::load();
+    let argv = command_line(app, files, options)?;
+    let mut parts = Vec::new();
+    if let Some(dir) = working_dir(app) {
+        parts.extend([String::from("cd"), shell_quote(dir), String::from("&&")]);
+    }
+    let env = launch_env(app, options, &config);
+    if !env.is_empty() {
+        parts.push(String::from("env"));
+        parts.extend(
+            env.into_iter()
+                .map(|(key, value)| shell_quote(&format!("{}={}", key, value))),
+        );
+    }
+    parts.extend(argv.iter().map(|arg| shell_quote(arg)));
+    Ok(parts.join(" "))
+}
+
+/// Environment set for `app` on top of the inherited one.
+fn launch_env(app: &AppEntry, options: &LaunchOptions, config: &Config) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    if let Some(token) = &options.activation_token {
+        env.push((String::from("XDG_ACTIVATION_TOKEN"), token.clone()));
+        env.push((String::from("DESKTOP_STARTUP_ID"), token.clone()));
+    }
+    if app.prefers_non_default_gpu {
+        env.extend(
+            gpu_offload_env()
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+    }
+    if let Some(app_env) = config.app_env.get(app.app_id()) {
+        env.extend(
+            app_env
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+    }
+    env
+}
+
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+fn working_dir(app: &AppEntry) -> Option<&str> {
+    let dir = app.working_dir.as_deref()?;
+    if Path::new(dir).is_dir() {
+        Some(dir)
+    } else {
+        warn!("Ignoring missing working directory {} for {}", dir, app.id);
+        None
+    }
+}
+
+/// Window rules for `app`: the configured `app_rules` when the Hyprland
+/// backend is selected, plus the workspace picked when activating it.
+fn hyprland_rules(app: &AppEntry, options: &LaunchOptions, config: &Config) -> Vec<String> {
+    let mut rules: Vec<String> = config
+        .app_rules
+        .get(app.app_id())
+        .filter(|_| config.launch_backend == LaunchBackend::Hyprland)
+        .map(|rules| {
+            rules
+                .split(';')
+                .map(str::trim)
+                .filter(|rule| !rule.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(workspace) = options.workspace {
+        rules.retain(|rule| !rule.starts_with("workspace"));
+        rules.push(format!("workspace {}", workspace));
+    }
+    rules
+}
+
+/// Builds the argv that [`command`] runs for `app`.
+pub fn command_line(
+    app: &AppEntry,
+    files: &[String],
+    options: &LaunchOptions,
+) -> io::Result<Vec<String>> {
+    let desktop_file = app.path.ends_with(".desktop").then_some(app.path.as_str());
+    let codes = FieldCodes {
+        name: &app.name,
+        icon: desktop_file.map(|_| app.icon_name.as_str()),
+        desktop_file,
+        files,
+    };
+
+    let argv = exec::expand(&app.exec, &codes).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid Exec line for {}: {:?}", app.id, app.exec),
+        )
+    })?;
+
+    if !app.terminal && !options.in_terminal {
+        return Ok(argv);
+    }
+
+    let mut terminal = terminal_command(&Config::load()).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "no terminal emulator found, set `terminal` in config.toml",
+        )
+    })?;
+    terminal.extend(argv);
+    Ok(terminal)
+}
+
+/// Resolves the configured terminal command, or the first installed one.
+fn terminal_command(config: &Config) -> Option<Vec<String>> {
+    if let Some(terminal) = &config.terminal {
+        let mut argv = exec::tokenize(terminal).filter(|argv| !argv.is_empty())?;
+        // A bare known terminal gets its exec arguments, so e.g.
+        // `terminal = "alacritty"` works without spelling out `-e`.
+        if let [program] = argv.as_slice()
+            && let Some(args) = known_exec_args(program)
+        {
+            argv.extend(args.iter().map(|arg| arg.to_string()));
+        }
+        return Some(argv);
+    }
+
+    let from_env = std::env::var("TERMINAL")
+        .ok()
+        .and_then(|terminal| exec::tokenize(&terminal))
+        .filter(|argv| !argv.is_empty());
+    let known = KNOWN_TERMINALS
+        .iter()
+        .map(|(program, _)| vec![program.to_string()]);
+
+    from_env
+        .into_iter()
+        .chain(known)
+        .find(|argv| launcher::find_executable(&argv[0]).is_some())
+        .map(|mut argv| {
+            argv.extend(terminal_exec_args(&argv[0]));
+            argv
+        })
+}
+
+/// Arguments that make `program` run the command that follows them. Unknown
+/// terminals get the conventional `-e`.
+fn terminal_exec_args(program: &str) -> Vec<String> {
+    known_exec_args(program)
+        .unwrap_or(&["-e"])
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+/// The exec arguments of `program` if it is one of [`KNOWN_TERMINALS`].
+fn known_exec_args(program: &str) -> Option<&'static [&'static str]> {
+    let name = Path::new(program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(program);
+    KNOWN_TERMINALS
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, args)| *args)
+}
+
+/// Environment that moves rendering to the discrete GPU: NVIDIA's PRIME
+/// render offload when the proprietary driver is loaded, Mesa's otherwise.
+fn gpu_offload_env() -> Vec<(&'static str, &'static str)> {
+    if Path::new("/proc/driver/nvidia/version").exists() {
+        vec![
+            ("__NV_PRIME_RENDER_OFFLOAD", "1"),
+            ("__GLX_VENDOR_LIBRARY_NAME", "nvidia"),
+            ("__VK_LAYER_NV_optimus", "NVIDIA_only"),
+        ]
+    } else {
+        vec![("DRI_PRIME", "1")]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_env_deny_keeps_launcher_settings_out() {
+        let config = Config::default();
+        for key in [
+            "LD_PRELOAD",
+            "GSK_RENDERER",
+            "GTK_DEBUG",
+            "LISTEN_FDS",
+            "RUST_LOG",
+        ] {
+            assert!(!is_inherited(&config, key), "{}", key);
+        }
+        for key in ["PATH", "WAYLAND_DISPLAY", "XDG_RUNTIME_DIR", "GTK_THEME"] {
+            assert!(is_inherited(&config, key), "{}", key);
+        }
+        assert!(!is_inherited(&config, "XDG_ACTIVATION_TOKEN"));
+    }
+
+    #[test]
+    fn env_allow_limits_and_env_deny_overrides() {
+        let config = Config {
+            env_allow: vec![String::from("XDG_*"), String::from("PATH")],
+            env_deny: vec![String::from("XDG_SESSION_ID")],
+            ..Default::default()
+        };
+        assert!(is_inherited(&config, "PATH"));
+        assert!(is_inherited(&config, "XDG_RUNTIME_DIR"));
+        assert!(!is_inherited(&config, "XDG_SESSION_ID"));
+        assert!(!is_inherited(&config, "HOME"));
+    }
+
+    fn configured(terminal: &str) -> Option<Vec<String>> {
+        let config = Config {
+            terminal: Some(terminal.to_string()),
+            ..Default::default()
+        };
+        terminal_command(&config)
+    }
+
+    #[test]
+    fn bare_known_terminal_gets_its_exec_args() {
+        assert_eq!(configured("alacritty").unwrap(), ["alacritty", "-e"]);
+        assert_eq!(
+            configured("/usr/bin/wezterm").unwrap(),
+            ["/usr/bin/wezterm", "start", "--"]
+        );
+        assert_eq!(configured("kitty").unwrap(), ["kitty"]);
+    }
+
+    #[test]
+    fn configured_arguments_are_kept_as_is() {
+        assert_eq!(
+            configured("foot --app-id term").unwrap(),
+            ["foot", "--app-id", "term"]
+        );
+        assert_eq!(configured("alacritty -e").unwrap(), ["alacritty", "-e"]);
+        assert_eq!(configured("my-term").unwrap(), ["my-term"]);
+        assert_eq!(configured(""), None);
+    }
+}
@@ -0,0 +1,368 @@
+//! Watching spawned children: one thread forwards their stderr to the
+//! launcher's, keeps the start of it for failure reports and reaps them once
+//! they exit.
+
+use crate::spawn;
+use log::warn;
+use std::fs::File;
+use std::io::{self, PipeReader, PipeWriter, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Amount of a child's stderr kept for its failure report.
+const KEPT_STDERR_BYTES: usize = 2048;
+
+/// How often children are checked for having exited when no pidfd tells.
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a child that exited within its grace period leaves for the report.
+struct Report {
+    kept: Vec<u8>,
+    tx: oneshot::Sender<(ExitStatus, Vec<u8>)>,
+}
+
+struct Supervised {
+    /// `None` once reaped.
+    child: Option<Child>,
+    /// Readable once the child exited.
+    pidfd: Option<OwnedFd>,
+    /// Read end of the child's stderr, `None` once closed.
+    stderr: Option<File>,
+    /// Set while the launch waits for the child to fail.
+    report: Option<Report>,
+}
+
+impl Supervised {
+    fn update(&mut self, buffer: &mut [u8]) {
+        let status = match self.child.as_mut().map(Child::try_wait) {
+            Some(Ok(status)) => status,
+            Some(Err(e)) => {
+                warn!("Failed to check on a launched app: {}", e);
+                self.child = None;
+                None
+            }
+            None => None,
+        };
+        if status.is_some() {
+            self.child = None;
+            self.pidfd = None;
+        }
+        // Everything the child itself wrote is in the pipe once it exited.
+        self.forward(buffer);
+
+        if let Some(report) = self
+            .report
+            .take_if(|report| status.is_some() || report.tx.is_closed())
+            && let Some(status) = status
+        {
+            let _ = report.tx.send((status, report.kept));
+        }
+    }
+
+    /// Passes on what is waiting in the child's stderr, keeping the start
+    /// of it for the report.
+    fn forward(&mut self, buffer: &mut [u8]) {
+        let Some(stderr) = &mut self.stderr else {
+            return;
+        };
+        let open = loop {
+            match stderr.read(buffer) {
+                Ok(0) => break false,
+                Ok(read) => {
+                    if let Some(report) = &mut self.report {
+                        let room = KEPT_STDERR_BYTES.saturating_sub(report.kept.len());
+                        report.kept.extend_from_slice(&buffer[..read.min(room)]);
+                    }
+                    let _ = io::stderr().write_all(&buffer[..read]);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break false,
+            }
+        };
+        if !open {
+            self.stderr = None;
+        }
+    }
+}
+
+struct Supervisor {
+    children: Mutex<Vec<Supervised>>,
+    /// Wakes the thread up to look at the children again.
+    wake: PipeWriter,
+}
+
+impl Supervisor {
+    /// Has the thread poll the current children, since a descriptor it
+    /// still polls keeps its file open.
+    fn wake(&self) {
+        let _ = (&self.wake).write(&[0]);
+    }
+}
+
+static SUPERVISOR: OnceLock<Supervisor> = OnceLock::new();
+
+/// The supervisor, started on first use.
+fn supervisor() -> &'static Supervisor {
+    SUPERVISOR.get_or_init(|| {
+        let (reader, writer) = io::pipe().expect("create the supervisor's wake-up pipe");
+        set_nonblocking(reader.as_raw_fd(), true).expect("set up the wake-up pipe");
+        std::thread::Builder::new()
+            .name(String::from("supervisor"))
+            .spawn(move || run(reader))
+            .expect("start the supervisor thread");
+        Supervisor {
+            children: Mutex::default(),
+            wake: writer,
+        }
+    })
+}
+
+fn run(mut wake: PipeReader) {
+    let mut buffer = vec![0; 8192];
+    loop {
+        let (mut fds, timeout) = {
+            let children = supervisor().children.lock().unwrap();
+            let mut fds = vec![wake.as_raw_fd()];
+            for supervised in children.iter() {
+                fds.extend(supervised.stderr.as_ref().map(File::as_raw_fd));
+                fds.extend(supervised.pidfd.as_ref().map(OwnedFd::as_raw_fd));
+            }
+            let polled = children
+                .iter()
+                .any(|supervised| supervised.child.is_some() && supervised.pidfd.is_none());
+            let timeout = if polled {
+                REAP_INTERVAL.as_millis() as i32
+            } else {
+                -1
+            };
+            (fds.into_iter().map(pollfd).collect::<Vec<_>>(), timeout)
+        };
+        // Which descriptors are ready does not matter: every child is looked
+        // at after each wake-up, all reads being non-blocking.
+        // SAFETY: `fds` is a valid array of `fds.len()` entries.
+        unsafe {
+            libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout);
+        }
+        while wake.read(&mut buffer).is_ok_and(|read| read > 0) {}
+
+        let mut children = supervisor().children.lock().unwrap();
+        for supervised in children.iter_mut() {
+            supervised.update(&mut buffer);
+        }
+        children.retain(|supervised| supervised.child.is_some() || supervised.stderr.is_some());
+    }
+}
+
+fn pollfd(fd: RawFd) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }
+}
+
+/// Watches `child` for `grace` and reports it exiting with an error in that
+/// time, along with the start of its stderr, which should be piped. Its
+/// stderr keeps being forwarded and the child is reaped in the background.
+pub async fn supervise(mut child: Child, grace: Duration) -> io::Result<()> {
+    let stderr = child
+        .stderr
+        .take()
+        .map(|stderr| File::from(OwnedFd::from(stderr)));
+    if let Some(stderr) = &stderr {
+        set_nonblocking(stderr.as_raw_fd(), true)?;
+    }
+    let pidfd = pidfd_open(child.id());
+    let (tx, rx) = oneshot::channel();
+
+    let supervisor = supervisor();
+    supervisor.children.lock().unwrap().push(Supervised {
+        child: Some(child),
+        pidfd,
+        stderr,
+        report: Some(Report {
+            kept: Vec::new(),
+            tx,
+        }),
+    });
+    supervisor.wake();
+
+    let Ok(Ok((status, kept))) = tokio::time::timeout(grace, rx).await else {
+        return Ok(());
+    };
+    if status.success() {
+        return Ok(());
+    }
+    let kept = String::from_utf8_lossy(&kept);
+    let kept = kept.trim();
+    let message = if kept.is_empty() {
+        format!("exited with {}", status)
+    } else {
+        format!("exited with {}:\n{}", status, kept)
+    };
+    Err(io::Error::other(message))
+}
+
+/// Hands the stderr of the children still writing to it to a `cat` of
+/// their own, so they keep a reader once the launcher exited instead of
+/// dying of `SIGPIPE`.
+pub fn hand_off() {
+    let Some(supervisor) = SUPERVISOR.get() else {
+        return;
+    };
+    let mut buffer = vec![0; 8192];
+    let mut children = supervisor.children.lock().unwrap();
+    for supervised in children.iter_mut() {
+        if supervised
+            .report
+            .as_ref()
+            .is_some_and(|report| !report.tx.is_closed())
+        {
+            continue;
+        }
+        supervised.forward(&mut buffer);
+        if let Some(stderr) = supervised.stderr.take()
+            && let Err(e) = forward_in_background(stderr)
+        {
+            warn!("Failed to pass on the stderr of a launched app: {}", e);
+        }
+    }
+    supervisor.wake();
+}
+
+/// Copies `stderr` to the launcher's stderr from a process of its own.
+fn forward_in_background(stderr: File) -> io::Result<()> {
+    set_nonblocking(stderr.as_raw_fd(), false)?;
+    // The shell exits right away, leaving `cat` to init, so nobody has to
+    // reap it. Asynchronous commands get /dev/null as stdin, hence fd 3.
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "exec 3<&0; cat <&3 >&2 &"])
+        .stdin(stderr)
+        .stdout(Stdio::null());
+    // SAFETY: only async-signal-safe calls are made between fork and exec.
+    unsafe {
+        command.pre_exec(spawn::detach);
+    }
+    let status = command.status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("sh exited with {}", status)));
+    }
+    Ok(())
+}
+
+/// A descriptor that becomes readable once process `pid` exited, if the
+/// kernel supports them.
+fn pidfd_open(pid: u32) -> Option<OwnedFd> {
+    // SAFETY: a plain syscall; a returned descriptor is owned by nothing else.
+    unsafe {
+        let fd = libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0);
+        (fd >= 0).then(|| OwnedFd::from_raw_fd(fd as RawFd))
+    }
+}
+
+fn set_nonblocking(fd: RawFd, nonblocking: bool) -> io::Result<()> {
+    // SAFETY: `fd` is open for the duration of the calls.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let flags = if nonblocking {
+            flags | libc::O_NONBLOCK
+        } else {
+            flags & !libc::O_NONBLOCK
+        };
+        if libc::fcntl(fd, libc::F_SETFL, flags) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::path::Path;
+    use std::time::Instant;
+
+    /// Starts `script` the way launched apps start, with `SIGPIPE` back to
+    /// its default.
+    fn spawn(script: &str) -> Child {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]).stderr(Stdio::piped());
+        // SAFETY: only async-signal-safe calls are made between fork and exec.
+        unsafe {
+            command.pre_exec(spawn::detach);
+        }
+        command.spawn().unwrap()
+    }
+
+    fn eventually(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn early_failure_reports_the_start_of_stderr() {
+        let child = spawn(
+            "echo starting >&2; head -c 4096 /dev/zero | tr '\\0' x >&2; echo boom >&2; exit 3",
+        );
+        let error = supervise(child, Duration::from_secs(5))
+            .await
+            .unwrap_err()
+            .to_string();
+        let report = error
+            .strip_prefix("exited with exit status: 3:\nstarting\n")
+            .unwrap_or_else(|| panic!("{}", error));
+        assert_eq!(report, "x".repeat(KEPT_STDERR_BYTES - "starting\n".len()));
+    }
+
+    #[tokio::test]
+    async fn failure_without_output_reports_the_status() {
+        let error = supervise(spawn("exit 1"), Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "exited with exit status: 1");
+    }
+
+    #[tokio::test]
+    async fn success_is_not_an_error() {
+        let child = spawn("echo warning >&2");
+        supervise(child, Duration::from_secs(5)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn children_are_reaped_after_the_grace_period() {
+        let child = spawn("sleep 0.2; exit 1");
+        let proc = format!("/proc/{}", child.id());
+        supervise(child, Duration::from_millis(10)).await.unwrap();
+        assert!(Path::new(&proc).exists());
+        assert!(eventually(|| !Path::new(&proc).exists()));
+    }
+
+    #[tokio::test]
+    async fn stderr_still_has_a_reader_after_hand_off() {
+        let dir = TempDir::new();
+        let done = dir.path().join("done");
+        let child = spawn(&format!(
+            "sleep 0.3; echo late >&2 && touch '{}'",
+            done.display()
+        ));
+        supervise(child, Duration::from_millis(10)).await.unwrap();
+        hand_off();
+        assert!(eventually(|| done.exists()));
+    }
+}
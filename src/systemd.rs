@@ -0,0 +1,152 @@
+//! Moving launched processes into their own systemd user scope, named after
+//! the XDG cgroup convention `app-<launcher>-<ApplicationID>-<RANDOM>.scope`.
+
+use crate::launcher::AppEntry;
+use std::hash::{BuildHasher, RandomState};
+use zbus::zvariant::{OwnedObjectPath, Value};
+use zbus::{Connection, proxy};
+
+#[proxy(
+    interface = "org.freedesktop.systemd1.Manager",
+    default_service = "org.freedesktop.systemd1",
+    default_path = "/org/freedesktop/systemd1"
+)]
+trait Manager {
+    fn start_transient_unit(
+        &self,
+        name: &str,
+        mode: &str,
+        properties: &[(&str, Value<'_>)],
+        aux: &[(&str, &[(&str, Value<'_>)])],
+    ) -> zbus::Result<OwnedObjectPath>;
+}
+
+/// Creates a transient scope for `app` on the user manager and moves `pid`
+/// into it.
+pub async fn move_to_scope(app: &AppEntry, pid: u32) -> zbus::Result<()> {
+    let connection = Connection::session().await?;
+    move_to_scope_on(&connection, app, pid).await
+}
+
+/// Asks the manager reachable through `connection` for the scope; see
+/// [`move_to_scope`].
+pub async fn move_to_scope_on(
+    connection: &Connection,
+    app: &AppEntry,
+    pid: u32,
+) -> zbus::Result<()> {
+    let manager = ManagerProxy::new(connection).await?;
+    let unit = scope_name(app);
+    let description = format!("{} launched by hyprlauncher", app.name);
+    let properties = [
+        ("PIDs", Value::from(vec![pid])),
+        ("Description", Value::from(description.as_str())),
+        ("CollectMode", Value::from("inactive-or-failed")),
+    ];
+
+    manager
+        .start_transient_unit(&unit, "fail", &properties, &[])
+        .await?;
+    Ok(())
+}
+
+fn scope_name(app: &AppEntry) -> String {
+    let application_id = app.app_id().trim_end_matches(".desktop");
+    let random = RandomState::new().hash_one(app.id.as_str());
+    format!(
+        "app-hyprlauncher-{}-{:016x}.scope",
+        escape(application_id),
+        random
+    )
+}
+
+/// Escapes `name` like `systemd-escape` does, so dashes inside the ID don't
+/// read as unit name separators.
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for (i, byte) in name.bytes().enumerate() {
+        let keep =
+            byte.is_ascii_alphanumeric() || matches!(byte, b':' | b'_') || (byte == b'.' && i > 0);
+        if keep {
+            escaped.push(byte as char);
+        } else if byte == b'/' {
+            escaped.push('-');
+        } else {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::PrivateBus;
+    use std::sync::{Arc, Mutex};
+    use zbus::zvariant::OwnedValue;
+
+    /// Name, mode and PIDs of a unit the stub was asked to start.
+    type Started = (String, String, Vec<u32>);
+
+    /// Records the units it is asked to start.
+    struct StubManager(Arc<Mutex<Vec<Started>>>);
+
+    #[zbus::interface(name = "org.freedesktop.systemd1.Manager")]
+    impl StubManager {
+        fn start_transient_unit(
+            &self,
+            name: String,
+            mode: String,
+            properties: Vec<(String, OwnedValue)>,
+            _aux: Vec<(String, Vec<(String, OwnedValue)>)>,
+        ) -> OwnedObjectPath {
+            let pids = properties
+                .into_iter()
+                .find(|(key, _)| key == "PIDs")
+                .and_then(|(_, value)| Vec::<u32>::try_from(value).ok())
+                .unwrap_or_default();
+            self.0.lock().unwrap().push((name, mode, pids));
+            OwnedObjectPath::try_from("/org/freedesktop/systemd1/job/1").unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn starts_a_transient_scope_holding_the_pid() {
+        let bus = PrivateBus::start();
+        let units = Arc::new(Mutex::new(Vec::new()));
+        let _manager = bus
+            .builder()
+            .name("org.freedesktop.systemd1")
+            .unwrap()
+            .serve_at("/org/freedesktop/systemd1", StubManager(units.clone()))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let connection = bus.connect().await;
+        let app = AppEntry {
+            id: String::from("org.gnome.Calculator.desktop"),
+            name: String::from("Calculator"),
+            ..Default::default()
+        };
+
+        move_to_scope_on(&connection, &app, 4242).await.unwrap();
+
+        let units = units.lock().unwrap();
+        let [(name, mode, pids)] = units.as_slice() else {
+            panic!("expected one unit, got {:?}", units);
+        };
+        assert!(name.starts_with("app-hyprlauncher-org.gnome.Calculator-"));
+        assert!(name.ends_with(".scope"));
+        assert_eq!(mode, "fail");
+        assert_eq!(pids, &[4242]);
+    }
+
+    #[test]
+    fn escape_matches_systemd_escape() {
+        assert_eq!(escape("org.gnome.Calculator"), "org.gnome.Calculator");
+        assert_eq!(escape("my-app"), "my\\x2dapp");
+        assert_eq!(escape(".hidden"), "\\x2ehidden");
+        assert_eq!(escape("a b"), "a\\x20b");
+    }
+}
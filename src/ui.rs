@@ -1,26 +1,38 @@
 use crate::config::Config;
+use crate::control::{self, Command, Event};
 use crate::launcher::{self, AppEntry, EntryType};
-use crate::search;
+use crate::notification;
+use crate::provider::{self, Activation, Searches};
+use crate::search::{self, SearchMode, SearchResult};
+use crate::spawn::{self, LaunchOptions};
+use crate::watcher;
 use gtk4::ListBoxRow;
-use gtk4::gdk::Key;
+use gtk4::gdk::{Key, ModifierType};
 use gtk4::glib::{self, clone};
 use gtk4::prelude::*;
 use gtk4::{Application, ApplicationWindow, Label, ListBox, ScrolledWindow, SearchEntry};
 use gtk4::{Box as GtkBox, CssProvider, Orientation, STYLE_PROVIDER_PRIORITY_APPLICATION};
-use gtk4_layer_shell::{Edge, KeyboardMode, Layer, LayerShell};
+use gtk4_layer_shell::{KeyboardMode, Layer, LayerShell};
 use std::cell::RefCell;
-use std::process::Command;
 use std::rc::Rc;
 
+#[derive(Clone)]
 pub struct LauncherWindow {
     window: ApplicationWindow,
     search_entry: SearchEntry,
+    scrolled: ScrolledWindow,
     results_list: ListBox,
-    app_data_store: Rc<RefCell<Vec<AppEntry>>>,
+    /// The listed results, row by row.
+    app_data_store: Rc<RefCell<Vec<SearchResult>>>,
+    /// The search the list shows the results of.
+    searches: Rc<RefCell<Searches>>,
+    mode: Rc<RefCell<SearchMode>>,
 }
 
 impl LauncherWindow {
-    pub fn new(app: &Application) -> Self {
+    /// Builds the launcher window. A `resident` window is only hidden when
+    /// closed, so it can be shown again without being rebuilt.
+    pub fn new(app: &Application, resident: bool) -> Self {
         let config = Config::load();
         let window = ApplicationWindow::builder()
             .application(app)
@@ -30,6 +42,7 @@ impl LauncherWindow {
             .decorated(false)
             .resizable(false)
             .modal(true)
+            .hide_on_close(resident)
             .build();
         window.init_layer_shell();
         window.set_layer(Layer::Overlay);
@@ -68,22 +81,16 @@ impl LauncherWindow {
         main_box.append(&search_entry);
         main_box.append(&scrolled);
         window.set_child(Some(&main_box));
-
-        let css = CssProvider::new();
-        css.load_from_data(&Config::load_css());
-
-        let display = window.native().unwrap().display();
-        gtk4::style_context_add_provider_for_display(
-            &display,
-            &css,
-            STYLE_PROVIDER_PRIORITY_APPLICATION,
-        );
+        install_css(&window);
 
         let launcher = Self {
             window,
             search_entry,
+            scrolled,
             results_list,
             app_data_store: Rc::new(RefCell::new(Vec::new())),
+            searches: Rc::new(RefCell::new(Searches::default())),
+            mode: Rc::new(RefCell::new(SearchMode::default())),
         };
 
         launcher.setup_signals();
@@ -96,6 +103,14 @@ impl LauncherWindow {
         let app_data_store = self.app_data_store.clone();
         let search_counter = Rc::new(RefCell::new(0u32));
 
+        // Every way of closing the window ends up here, so a resident window
+        // starts over the next time it is shown.
+        let launcher = self.clone();
+        self.window.connect_hide(move |_| {
+            launcher.reset();
+            control::emit(Event::Closed);
+        });
+
         self.search_entry.connect_changed(clone!(
             #[strong]
             results_list,
@@ -103,7 +118,13 @@ impl LauncherWindow {
             app_data_store,
             #[strong]
             search_counter,
+            #[strong(rename_to = searches)]
+            self.searches,
+            #[strong(rename_to = mode)]
+            self.mode,
             move |entry| {
+                // The results of the running search are stale by now.
+                searches.borrow().cancel();
                 let current_counter = {
                     let mut counter = search_counter.borrow_mut();
                     *counter = counter.wrapping_add(1);
@@ -120,20 +141,34 @@ impl LauncherWindow {
                         app_data_store,
                         #[strong]
                         search_counter,
+                        #[strong]
+                        searches,
+                        #[strong]
+                        mode,
                         move || {
                             if current_counter != *search_counter.borrow() {
                                 return glib::ControlFlow::Break;
                             }
 
                             let query = query.clone();
+                            let mode = mode.borrow().clone();
                             glib::spawn_future_local(clone!(
                                 #[strong]
                                 results_list,
                                 #[strong]
                                 app_data_store,
+                                #[strong]
+                                searches,
                                 async move {
-                                    let results = search::search_applications(&query).await;
-                                    update_results_list(&results_list, results, &app_data_store);
+                                    show_results(
+                                        &results_list,
+                                        &app_data_store,
+                                        &searches,
+                                        &query,
+                                        &mode,
+                                        false,
+                                    )
+                                    .await;
                                 }
                             ));
                             glib::ControlFlow::Break
@@ -150,11 +185,11 @@ impl LauncherWindow {
             move |_, key, _, _| {
                 match key {
                     Key::Escape => {
-                        if let Some(row) = results_list.first_child() {
-                            if let Some(list_row) = row.downcast_ref::<ListBoxRow>() {
-                                results_list.select_row(Some(list_row));
-                                list_row.grab_focus();
-                            }
+                        if let Some(row) = results_list.first_child()
+                            && let Some(list_row) = row.downcast_ref::<ListBoxRow>()
+                        {
+                            results_list.select_row(Some(list_row));
+                            list_row.grab_focus();
                         }
                         glib::Propagation::Stop
                     }
@@ -168,6 +203,8 @@ impl LauncherWindow {
         window_controller.connect_key_pressed(clone!(
             #[strong]
             results_list,
+            #[strong]
+            app_data_store,
             #[strong(rename_to = window)]
             self.window,
             #[strong(rename_to = search_entry)]
@@ -177,11 +214,11 @@ impl LauncherWindow {
                     Key::Escape => {
                         if search_entry.has_focus() {
                             if search_entry.text().is_empty() {
-                                if let Some(row) = results_list.first_child() {
-                                    if let Some(list_row) = row.downcast_ref::<ListBoxRow>() {
-                                        results_list.select_row(Some(list_row));
-                                        list_row.grab_focus();
-                                    }
+                                if let Some(row) = results_list.first_child()
+                                    && let Some(list_row) = row.downcast_ref::<ListBoxRow>()
+                                {
+                                    results_list.select_row(Some(list_row));
+                                    list_row.grab_focus();
                                 }
                             } else {
                                 search_entry.set_text("");
@@ -207,6 +244,10 @@ impl LauncherWindow {
                         }
                         glib::Propagation::Stop
                     }
+                    Key::Tab => {
+                        toggle_actions(&results_list, &app_data_store);
+                        glib::Propagation::Stop
+                    }
                     _ => glib::Propagation::Proceed,
                 }
             }
@@ -222,9 +263,7 @@ impl LauncherWindow {
             app_data_store,
             move |_, row| {
                 if let Some(app_data) = get_app_data(row.index() as usize, &app_data_store) {
-                    if launch_application(&app_data, &search_entry) {
-                        window.hide();
-                    }
+                    launch_application(&app_data, &window, &search_entry, LaunchOptions::default());
                 }
             }
         ));
@@ -239,15 +278,56 @@ impl LauncherWindow {
             #[strong]
             app_data_store,
             move |_| {
-                if let Some(row) = results_list.selected_row() {
-                    if let Some(app_data) = get_app_data(row.index() as usize, &app_data_store) {
-                        if launch_application(&app_data, &search_entry) {
-                            window.close();
-                        }
+                if let Some(row) = results_list.selected_row()
+                    && let Some(app_data) = get_app_data(row.index() as usize, &app_data_store)
+                {
+                    launch_application(&app_data, &window, &search_entry, LaunchOptions::default());
+                }
+            }
+        ));
+
+        // Runs in the capture phase so modified key presses are seen before
+        // the search entry turns them into a plain `activate` or text.
+        let modifier_controller = gtk4::EventControllerKey::new();
+        modifier_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+        modifier_controller.connect_key_pressed(clone!(
+            #[strong]
+            results_list,
+            #[strong(rename_to = window)]
+            self.window,
+            #[strong(rename_to = search_entry)]
+            self.search_entry,
+            #[strong]
+            app_data_store,
+            move |_, key, _, state| {
+                let options = if matches!(key, Key::Return | Key::KP_Enter)
+                    && state.contains(ModifierType::CONTROL_MASK)
+                {
+                    LaunchOptions {
+                        in_terminal: true,
+                        ..Default::default()
                     }
+                } else if state.contains(ModifierType::ALT_MASK)
+                    && let Some(workspace) = key.to_unicode().and_then(|c| c.to_digit(10))
+                {
+                    // Alt+1 to Alt+9 open on that workspace, Alt+0 on the 10th.
+                    LaunchOptions {
+                        workspace: Some(if workspace == 0 { 10 } else { workspace }),
+                        ..Default::default()
+                    }
+                } else {
+                    return glib::Propagation::Proceed;
+                };
+
+                if let Some(row) = results_list.selected_row()
+                    && let Some(app_data) = get_app_data(row.index() as usize, &app_data_store)
+                {
+                    launch_application(&app_data, &window, &search_entry, options);
                 }
+                glib::Propagation::Stop
             }
         ));
+        self.window.add_controller(modifier_controller);
     }
 
     fn load_applications(&self) {
@@ -259,10 +339,41 @@ impl LauncherWindow {
             results_list,
             #[strong]
             app_data_store,
+            #[strong(rename_to = search_entry)]
+            self.search_entry,
+            #[strong(rename_to = searches)]
+            self.searches,
+            #[strong(rename_to = mode)]
+            self.mode,
             async move {
+                watcher::watch_applications();
+                let mut index_updates = watcher::subscribe();
                 launcher::load_applications().await;
-                let results = search::search_applications("").await;
-                update_results_list(&results_list, results, &app_data_store);
+                let current_mode = mode.borrow().clone();
+                let query = search_entry.text();
+                show_results(
+                    &results_list,
+                    &app_data_store,
+                    &searches,
+                    &query,
+                    &current_mode,
+                    false,
+                )
+                .await;
+
+                while index_updates.changed().await.is_ok() {
+                    let current_mode = mode.borrow().clone();
+                    let query = search_entry.text();
+                    show_results(
+                        &results_list,
+                        &app_data_store,
+                        &searches,
+                        &query,
+                        &current_mode,
+                        true,
+                    )
+                    .await;
+                }
             }
         ));
     }
@@ -271,32 +382,217 @@ impl LauncherWindow {
         self.window.present();
         self.search_entry.grab_focus();
     }
+
+    /// Whether the window still belongs to the application, i.e. was not
+    /// closed for good.
+    pub fn is_alive(&self) -> bool {
+        self.window.application().is_some()
+    }
+
+    /// Hides the window if it is shown, otherwise shows it.
+    pub fn toggle(&self) {
+        if self.window.is_visible() {
+            self.window.close();
+        } else {
+            self.present();
+        }
+    }
+
+    /// Carries out a request received over D-Bus.
+    pub fn handle(&self, command: Command) {
+        match command {
+            Command::Show => self.present(),
+            Command::Hide => self.window.close(),
+            Command::Toggle => self.toggle(),
+            Command::SetQuery(query) => {
+                self.search_entry.set_text(&query);
+                self.search_entry.set_position(-1);
+            }
+            Command::SetMode(mode) => {
+                self.mode.replace(mode);
+                self.refresh();
+            }
+        }
+    }
+
+    /// Runs the current query again right away.
+    fn refresh(&self) {
+        let query = self.search_entry.text().to_string();
+        let mode = self.mode.borrow().clone();
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = results_list)]
+            self.results_list,
+            #[strong(rename_to = app_data_store)]
+            self.app_data_store,
+            #[strong(rename_to = searches)]
+            self.searches,
+            async move {
+                show_results(
+                    &results_list,
+                    &app_data_store,
+                    &searches,
+                    &query,
+                    &mode,
+                    false,
+                )
+                .await;
+            }
+        ));
+    }
+
+    /// Clears the query and mode left over from the last time the window
+    /// was shown.
+    fn reset(&self) {
+        self.mode.replace(SearchMode::default());
+        if self.search_entry.text().is_empty() {
+            // Setting the same text emits no `changed`, so refresh the results
+            // directly to drop expanded actions and pick up new launch counts.
+            self.refresh();
+        } else {
+            self.search_entry.set_text("");
+        }
+        self.scrolled.vadjustment().set_value(0.0);
+    }
+}
+
+/// Applies the user's stylesheet to the display `window` is on.
+pub fn install_css(window: &ApplicationWindow) {
+    let css = CssProvider::new();
+    css.load_from_data(&Config::load_css());
+
+    let display = window.native().unwrap().display();
+    gtk4::style_context_add_provider_for_display(
+        &display,
+        &css,
+        STYLE_PROVIDER_PRIORITY_APPLICATION,
+    );
 }
 
-fn get_app_data(index: usize, store: &Rc<RefCell<Vec<AppEntry>>>) -> Option<AppEntry> {
-    store.borrow().get(index).cloned()
+fn get_app_data(index: usize, store: &Rc<RefCell<Vec<SearchResult>>>) -> Option<AppEntry> {
+    store.borrow().get(index).map(|result| result.app.clone())
 }
 
-fn update_results_list(
+/// Lists the results for `query`, replacing the rows of the previous query
+/// once the first ones are in and merging the others in as the providers
+/// send them. Starting it cancels the search running before, and it stops
+/// once another one starts. The top row stays selected as better results
+/// come in, unless the user picked another one. With `keep_selection` the
+/// previously selected entry is selected again when it shows up.
+async fn show_results(
     list: &ListBox,
-    results: Vec<search::SearchResult>,
-    store: &Rc<RefCell<Vec<AppEntry>>>,
+    store: &Rc<RefCell<Vec<SearchResult>>>,
+    searches: &RefCell<Searches>,
+    query: &str,
+    mode: &SearchMode,
+    keep_selection: bool,
 ) {
+    let mut selected_id = list
+        .selected_row()
+        .filter(|_| keep_selection)
+        .and_then(|row| get_app_data(row.index() as usize, store))
+        .map(|app| app.id);
+
+    // The entry this search selected itself, as opposed to the user.
+    let mut auto_selected = None;
+
+    let (generation, cancellation) = searches.borrow_mut().start();
+    let mut batches = provider::search(query, mode, generation, cancellation.clone());
+    let mut cleared = false;
+    while let Some(batch) = batches.recv().await {
+        // Batches sent before the search was cancelled may still be queued.
+        if !searches.borrow().is_current(batch.generation) {
+            return;
+        }
+        if !cleared {
+            clear_results(list, store);
+            cleared = true;
+        }
+        merge_results(list, batch.results, store);
+
+        let reselect = selected_id.as_ref().and_then(|id| {
+            let index = store
+                .borrow()
+                .iter()
+                .position(|result| &result.app.id == id)?;
+            list.row_at_index(index as i32)
+        });
+        let selected = list
+            .selected_row()
+            .and_then(|row| get_app_data(row.index() as usize, store))
+            .map(|app| app.id);
+        if let Some(row) = reselect {
+            list.select_row(Some(&row));
+            selected_id = None;
+            auto_selected = None;
+        } else if (selected.is_none() || selected == auto_selected)
+            && let Some(row) = list.row_at_index(0)
+        {
+            list.select_row(Some(&row));
+            auto_selected = get_app_data(0, store).map(|app| app.id);
+        }
+    }
+    // Nothing found; a cancelled search leaves the rows to the next one.
+    if !cleared && !cancellation.is_cancelled() {
+        clear_results(list, store);
+    }
+}
+
+fn clear_results(list: &ListBox, store: &Rc<RefCell<Vec<SearchResult>>>) {
     while let Some(child) = list.first_child() {
         list.remove(&child);
     }
+    store.borrow_mut().clear();
+}
 
+/// Inserts `results` by score between the rows already listed, which stay
+/// where they are, so the selection and scroll position are kept. A better
+/// ranked duplicate replaces the listed one.
+fn merge_results(
+    list: &ListBox,
+    results: Vec<SearchResult>,
+    store: &Rc<RefCell<Vec<SearchResult>>>,
+) {
     let mut store = store.borrow_mut();
-    store.clear();
-
     for result in results {
-        store.push(result.app.clone());
-        let row = create_result_row(&result.app);
-        list.append(&row);
+        if let Some(index) = store
+            .iter()
+            .position(|listed| search::is_duplicate(&listed.app, &result.app))
+        {
+            if store[index].score >= result.score {
+                continue;
+            }
+            remove_result(list, &mut store, index);
+        }
+
+        let index = store
+            .iter()
+            .position(|listed| listed.score < result.score)
+            .unwrap_or(store.len());
+        if index >= provider::MAX_RESULTS {
+            continue;
+        }
+        list.insert(&create_result_row(&result.app), index as i32);
+        store.insert(index, result);
+        if store.len() > provider::MAX_RESULTS {
+            remove_result(list, &mut store, provider::MAX_RESULTS);
+        }
     }
+}
 
-    if let Some(first_row) = list.row_at_index(0) {
-        list.select_row(Some(&first_row));
+/// Removes the row at `index`, along with its actions if they are expanded.
+fn remove_result(list: &ListBox, store: &mut Vec<SearchResult>, index: usize) {
+    let removed = store.remove(index);
+    if let Some(row) = list.row_at_index(index as i32) {
+        list.remove(&row);
+    }
+    while store
+        .get(index)
+        .is_some_and(|next| is_action_of(&next.app, &removed.app))
+    {
+        store.remove(index);
+        if let Some(row) = list.row_at_index(index as i32) {
+            list.remove(&row);
+        }
     }
 }
 
@@ -328,71 +624,141 @@ fn create_result_row(app: &AppEntry) -> gtk4::ListBoxRow {
 
     box_row.append(&text_box);
     row.set_child(Some(&box_row));
+    if matches!(
+        app.entry_type,
+        EntryType::Action | EntryType::ProviderAction(_)
+    ) {
+        row.add_css_class("app-action");
+    }
     row
 }
 
-fn select_next(list: &ListBox) {
-    if let Some(current) = list.selected_row() {
-        if let Some(next) = list.row_at_index(current.index() + 1) {
-            list.select_row(Some(&next));
-            next.grab_focus();
-        }
+/// Expands the selected application row to list its actions right
+/// below it, or collapses them again if they are already shown.
+fn toggle_actions(list: &ListBox, store: &Rc<RefCell<Vec<SearchResult>>>) {
+    let Some(selected) = list.selected_row() else {
+        return;
+    };
+    let index = selected.index() as usize;
+    let mut store = store.borrow_mut();
+    let Some((app, score)) = store
+        .get(index)
+        .map(|result| (result.app.clone(), result.score))
+    else {
+        return;
+    };
+    if !matches!(
+        app.entry_type,
+        EntryType::Application | EntryType::Provider(_)
+    ) || app.actions.is_empty()
+    {
+        return;
     }
-}
 
-fn select_previous(list: &ListBox) {
-    if let Some(current) = list.selected_row() {
-        if current.index() > 0 {
-            if let Some(prev) = list.row_at_index(current.index() - 1) {
-                list.select_row(Some(&prev));
-                prev.grab_focus();
+    let expanded = store
+        .get(index + 1)
+        .is_some_and(|next| is_action_of(&next.app, &app));
+
+    if expanded {
+        for _ in &app.actions {
+            if let Some(row) = list.row_at_index(index as i32 + 1) {
+                list.remove(&row);
             }
+            store.remove(index + 1);
+        }
+    } else {
+        for (offset, action) in app.actions.iter().enumerate() {
+            let position = index + 1 + offset;
+            let entry = app.action_entry(action);
+            list.insert(&create_result_row(&entry), position as i32);
+            // Ranked like the application, so merged results never land
+            // between it and its actions.
+            store.insert(position, SearchResult { app: entry, score });
         }
     }
 }
 
-fn launch_application(app: &AppEntry, search_entry: &SearchEntry) -> bool {
-    match app.entry_type {
-        EntryType::Application => {
-            let exec = app
-                .exec
-                .replace("%f", "")
-                .replace("%F", "")
-                .replace("%u", "")
-                .replace("%U", "")
-                .replace("%i", "")
-                .replace("%c", &app.name)
-                .trim()
-                .to_string();
-            let app_clone = app.clone();
-            glib::spawn_future_local(async move {
-                launcher::increment_launch_count(&app_clone).await;
-            });
-            glib::spawn_future_local(async move {
-                let _ = Command::new("sh").arg("-c").arg(exec).spawn();
-            });
-
-            true
-        }
-        EntryType::File => {
-            if app.icon_name == "folder" {
-                let path = if app.path.ends_with('/') {
-                    app.path.clone()
-                } else {
-                    format!("{}/", app.path)
-                };
-                search_entry.set_text(&path);
-                search_entry.set_position(-1);
+fn is_action_of(entry: &AppEntry, app: &AppEntry) -> bool {
+    matches!(
+        entry.entry_type,
+        EntryType::Action | EntryType::ProviderAction(_)
+    ) && entry.app_id() == app.id
+}
 
-                false
-            } else {
-                let exec = app.exec.clone();
-                glib::spawn_future_local(async move {
-                    let _ = Command::new("sh").arg("-c").arg(exec).spawn();
-                });
+fn select_next(list: &ListBox) {
+    if let Some(current) = list.selected_row()
+        && let Some(next) = list.row_at_index(current.index() + 1)
+    {
+        list.select_row(Some(&next));
+        next.grab_focus();
+    }
+}
 
-                true
+fn select_previous(list: &ListBox) {
+    if let Some(current) = list.selected_row()
+        && current.index() > 0
+        && let Some(prev) = list.row_at_index(current.index() - 1)
+    {
+        list.select_row(Some(&prev));
+        prev.grab_focus();
+    }
+}
+
+/// Activates `app` and, depending on what its provider decides, launches it
+/// and closes `window` or searches on.
+fn launch_application(
+    app: &AppEntry,
+    window: &ApplicationWindow,
+    search_entry: &SearchEntry,
+    mut options: LaunchOptions,
+) {
+    // Requested while the launcher still has keyboard focus, so the
+    // compositor lets the new window take it over.
+    if options.activation_token.is_none() {
+        options.activation_token = search_entry
+            .display()
+            .app_launch_context()
+            .startup_notify_id(None::<&gio::AppInfo>, &[])
+            .map(|token| token.to_string());
+    }
+
+    let app = app.clone();
+    glib::spawn_future_local(clone!(
+        #[weak]
+        window,
+        #[weak]
+        search_entry,
+        async move {
+            match provider::activate(&app).await {
+                Activation::Launch => {
+                    start(&app, options);
+                    window.close();
+                    if app.counts_launches() {
+                        launcher::increment_launch_count(&app).await;
+                    }
+                }
+                Activation::Query(query) => {
+                    search_entry.set_text(&query);
+                    search_entry.set_position(-1);
+                }
+                Activation::Close => window.close(),
             }
         }
-    }
+    ));
+}
+
+/// Launches `app` in the background and reports a failure through a desktop
+/// notification, since the window is gone by the time it is known.
+fn start(app: &AppEntry, options: LaunchOptions) {
+    // Launching finishes after the window closed, so keep the application
+    // alive until it is done.
+    let hold = gio::Application::default().map(|application| application.hold());
+    let app = app.clone();
+    control::emit(Event::Launched(app.id.clone()));
+    glib::spawn_future_local(async move {
+        if let Err(e) = spawn::launch(&app, &[], &options).await {
+            notification::launch_failed(&app, &e).await;
+        }
+        drop(hold);
+    });
 }
@@ -0,0 +1,233 @@
+//! Keeps the application index current while the launcher is running.
+
+use crate::launcher;
+use log::{error, info};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{LazyLock, Once};
+use std::time::Duration;
+use tokio::sync::watch;
+use walkdir::WalkDir;
+
+/// How long to wait for more events before touching the index, so that a
+/// package manager installing hundreds of files triggers one refresh.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Bumped every time the index changed after it was first loaded.
+static INDEX_GENERATION: LazyLock<watch::Sender<u64>> = LazyLock::new(|| watch::Sender::new(0));
+
+static WATCH: Once = Once::new();
+
+/// Tells every [`subscribe`]r that the index changed.
+pub fn index_changed() {
+    INDEX_GENERATION.send_modify(|generation| *generation += 1);
+}
+
+/// Returns a receiver that is notified whenever the index changed.
+pub fn subscribe() -> watch::Receiver<u64> {
+    INDEX_GENERATION.subscribe()
+}
+
+/// Starts watching every `applications` and `PATH` directory, once per
+/// process. Changes are applied to the index on a background thread.
+pub fn watch_applications() {
+    WATCH.call_once(|| {
+        if let Err(e) = std::thread::Builder::new()
+            .name(String::from("index-watcher"))
+            .spawn(run)
+        {
+            error!("Failed to start index watcher: {}", e);
+        }
+    });
+}
+
+fn run() {
+    let (tx, rx) = mpsc::channel();
+    let watcher = match notify::recommended_watcher(tx) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("Failed to create file watcher: {}", e);
+            return;
+        }
+    };
+
+    let app_dirs = launcher::application_dirs()
+        .into_iter()
+        .map(|dir| (dir, RecursiveMode::Recursive));
+    let path_dirs = launcher::path_dirs()
+        .into_iter()
+        .map(|dir| (dir, RecursiveMode::NonRecursive));
+    let mut watches = Watches::new(watcher, app_dirs.chain(path_dirs).collect());
+    watches.update();
+
+    while let Ok(event) = rx.recv() {
+        let mut changed = Vec::new();
+        collect_paths(event, &mut changed);
+        while let Ok(event) = rx.recv_timeout(DEBOUNCE) {
+            collect_paths(event, &mut changed);
+        }
+        // Files can land in a new directory before it is watched.
+        for (dir, mode) in watches.update() {
+            changed.extend(files_in(&dir, mode));
+        }
+        changed.sort();
+        changed.dedup();
+
+        // Changes seen while loading are applied on top of the whole index.
+        launcher::wait_until_loaded();
+        if launcher::refresh_entries(&changed) {
+            index_changed();
+        }
+    }
+}
+
+/// Watches a set of directories, some of which may not exist yet. For those
+/// the nearest existing ancestor is watched instead, so their creation is
+/// noticed.
+struct Watches<W> {
+    watcher: W,
+    targets: Vec<(PathBuf, RecursiveMode)>,
+    watched: HashSet<PathBuf>,
+    ancestors: HashSet<PathBuf>,
+}
+
+impl<W: Watcher> Watches<W> {
+    fn new(watcher: W, targets: Vec<(PathBuf, RecursiveMode)>) -> Self {
+        Self {
+            watcher,
+            targets,
+            watched: HashSet::new(),
+            ancestors: HashSet::new(),
+        }
+    }
+
+    /// Starts watching the directories that appeared since the last call and
+    /// moves the ancestor watches along. Returns the directories watched anew.
+    fn update(&mut self) -> Vec<(PathBuf, RecursiveMode)> {
+        let mut appeared = Vec::new();
+        loop {
+            let mut ancestors = HashSet::new();
+            for (dir, mode) in &self.targets {
+                if !dir.is_dir() {
+                    // The watch went away with the directory, if it had one.
+                    self.watched.remove(dir);
+                    if let Some(ancestor) = dir.ancestors().skip(1).find(|a| a.is_dir()) {
+                        ancestors.insert(ancestor.to_path_buf());
+                    }
+                } else if !self.watched.contains(dir) {
+                    match self.watcher.watch(dir, *mode) {
+                        Ok(()) => {
+                            info!("Watching {:?}", dir);
+                            self.watched.insert(dir.clone());
+                            appeared.push((dir.clone(), *mode));
+                        }
+                        Err(e) => error!("Failed to watch {:?}: {}", dir, e),
+                    }
+                }
+            }
+            ancestors.retain(|ancestor| !self.watched.contains(ancestor));
+            if ancestors == self.ancestors {
+                return appeared;
+            }
+
+            for ancestor in self.ancestors.difference(&ancestors) {
+                let _ = self.watcher.unwatch(ancestor);
+            }
+            for ancestor in ancestors.difference(&self.ancestors) {
+                match self.watcher.watch(ancestor, RecursiveMode::NonRecursive) {
+                    Ok(()) => info!("Watching {:?} for missing directories", ancestor),
+                    Err(e) => error!("Failed to watch {:?}: {}", ancestor, e),
+                }
+            }
+            // A directory may have been created before its parent was
+            // watched, so check again.
+            self.ancestors = ancestors;
+        }
+    }
+}
+
+/// The paths already inside `dir`, as far down as `mode` watches.
+fn files_in(dir: &Path, mode: RecursiveMode) -> Vec<PathBuf> {
+    let depth = match mode {
+        RecursiveMode::Recursive => usize::MAX,
+        RecursiveMode::NonRecursive => 1,
+    };
+    WalkDir::new(dir)
+        .follow_links(true)
+        .max_depth(depth)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(walkdir::DirEntry::into_path)
+        .collect()
+}
+
+fn collect_paths(event: notify::Result<notify::Event>, changed: &mut Vec<PathBuf>) {
+    match event {
+        Ok(event) if !matches!(event.kind, EventKind::Access(_)) => changed.extend(event.paths),
+        Ok(_) => {}
+        Err(e) => error!("File watcher error: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::time::Instant;
+
+    /// Waits for `watches` to pick up a directory, as [`run`] does after
+    /// each batch of events.
+    fn wait_for_update(
+        watches: &mut Watches<impl Watcher>,
+        rx: &mpsc::Receiver<notify::Result<notify::Event>>,
+    ) -> Vec<(PathBuf, RecursiveMode)> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            let _ = rx.recv_timeout(Duration::from_millis(100));
+            let appeared = watches.update();
+            if !appeared.is_empty() {
+                return appeared;
+            }
+        }
+        Vec::new()
+    }
+
+    #[test]
+    fn missing_directories_are_watched_once_created() {
+        let root = TempDir::new();
+        let applications = root.path().join("share/applications");
+        let (tx, rx) = mpsc::channel();
+        let watcher = notify::recommended_watcher(tx).unwrap();
+        let mut watches = Watches::new(
+            watcher,
+            vec![(applications.clone(), RecursiveMode::Recursive)],
+        );
+
+        assert!(watches.update().is_empty());
+        assert_eq!(
+            watches.ancestors,
+            HashSet::from([root.path().to_path_buf()])
+        );
+
+        let entry = root.write("share/applications/new.desktop", "[Desktop Entry]\n");
+        let appeared = wait_for_update(&mut watches, &rx);
+        assert_eq!(appeared.len(), 1);
+        assert_eq!(appeared[0].0, applications);
+        assert!(watches.ancestors.is_empty());
+        assert!(files_in(&applications, RecursiveMode::Recursive).contains(&entry));
+
+        // Files added later are reported by the directory's own watch.
+        while rx.try_recv().is_ok() {}
+        let later = root.write("share/applications/later.desktop", "[Desktop Entry]\n");
+        let mut changed = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !changed.contains(&later) && Instant::now() < deadline {
+            if let Ok(event) = rx.recv_timeout(Duration::from_millis(100)) {
+                collect_paths(event, &mut changed);
+            }
+        }
+        assert!(changed.contains(&later));
+    }
+}
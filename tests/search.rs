@@ -0,0 +1,211 @@
+//! Ranking checks through `hyprlauncher search --json`, run against a
+//! sandboxed set of XDG directories.
+
+use serde_json::Value;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A home directory with its own data, config and cache directories and
+/// `PATH`, removed on drop.
+struct Sandbox(PathBuf);
+
+impl Sandbox {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "hyprlauncher-search-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        for dir in ["home", "data/applications", "system/applications", "bin"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        Self(root)
+    }
+
+    fn write(&self, relative: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn desktop(&self, dir: &str, id: &str, fields: &str) {
+        self.write(
+            &format!("{}/applications/{}", dir, id),
+            &format!("[Desktop Entry]\nType=Application\n{}", fields),
+        );
+    }
+
+    fn binary(&self, name: &str) {
+        let path = self.write(&format!("bin/{}", name), "#!/bin/sh\n");
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn heatmap(&self, counts: &str) {
+        self.write(
+            "home/.local/share/hyprlauncher/heatmap.toml",
+            &format!("[map]\n{}", counts),
+        );
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        let path = |relative: &str| self.0.join(relative);
+        Command::new(env!("CARGO_BIN_EXE_hyprlauncher"))
+            .args(args)
+            .env_clear()
+            .env("HOME", path("home"))
+            .env("XDG_DATA_HOME", path("data"))
+            .env("XDG_DATA_DIRS", path("system"))
+            .env("XDG_CONFIG_HOME", path("config"))
+            .env("XDG_CACHE_HOME", path("cache"))
+            .env("PATH", path("bin"))
+            .env("LANG", "C")
+            .output()
+            .unwrap()
+    }
+
+    /// IDs of the results for `query`, best first, checking that scores
+    /// never go up along the way.
+    fn search(&self, args: &[&str]) -> Vec<String> {
+        let mut args = args.to_vec();
+        args.insert(0, "search");
+        args.push("--json");
+        let output = self.run(&args);
+        assert!(output.status.success(), "{:?}", output);
+        let results: Vec<Value> = serde_json::from_slice(&output.stdout).unwrap();
+        let scores: Vec<i64> = results
+            .iter()
+            .map(|r| r["score"].as_i64().unwrap())
+            .collect();
+        assert!(
+            scores.windows(2).all(|pair| pair[0] >= pair[1]),
+            "{:?}",
+            scores
+        );
+        results
+            .iter()
+            .map(|result| result["app"]["id"].as_str().unwrap().to_string())
+            // Only the sandbox's entries; Flatpak and Nix locations are
+            // scanned too.
+            .filter(|id| id.to_lowercase().starts_with("zq"))
+            .collect()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn position(ids: &[String], id: &str) -> usize {
+    ids.iter()
+        .position(|listed| listed == id)
+        .unwrap_or_else(|| panic!("{} missing from {:?}", id, ids))
+}
+
+#[test]
+fn name_matches_rank_above_keyword_matches() {
+    let sandbox = Sandbox::new();
+    sandbox.desktop(
+        "system",
+        "zq-notes.desktop",
+        "Name=Zq Notes\nKeywords=editor;\nIcon=notes\n",
+    );
+    sandbox.desktop(
+        "system",
+        "zq-editor.desktop",
+        "Name=Zq Editor\nIcon=editor\n",
+    );
+
+    let ids = sandbox.search(&["editor"]);
+    assert!(position(&ids, "zq-editor.desktop") < position(&ids, "zq-notes.desktop"));
+}
+
+#[test]
+fn launch_counts_break_ties() {
+    let sandbox = Sandbox::new();
+    sandbox.desktop(
+        "system",
+        "zq-term-one.desktop",
+        "Name=Zq Term One\nIcon=term\n",
+    );
+    sandbox.desktop(
+        "system",
+        "zq-term-two.desktop",
+        "Name=Zq Term Two\nIcon=term\n",
+    );
+    sandbox.heatmap("\"zq-term-two.desktop\" = 3\n");
+
+    let ids = sandbox.search(&["zq term"]);
+    assert_eq!(ids[0], "zq-term-two.desktop");
+    // The empty query lists applications by popularity alone.
+    let ids = sandbox.search(&[""]);
+    assert_eq!(ids[0], "zq-term-two.desktop");
+}
+
+#[test]
+fn entries_without_an_icon_rank_lower() {
+    let sandbox = Sandbox::new();
+    sandbox.desktop("system", "zq-plain.desktop", "Name=Zq Viewer A\n");
+    sandbox.desktop(
+        "system",
+        "zq-iconic.desktop",
+        "Name=Zq Viewer B\nIcon=viewer\n",
+    );
+
+    let ids = sandbox.search(&["zq viewer"]);
+    assert!(position(&ids, "zq-iconic.desktop") < position(&ids, "zq-plain.desktop"));
+}
+
+#[test]
+fn user_entries_shadow_system_ones() {
+    let sandbox = Sandbox::new();
+    sandbox.desktop("system", "zq-app.desktop", "Name=Zq System\n");
+    sandbox.desktop("data", "zq-app.desktop", "Name=Zq User\n");
+
+    assert_eq!(sandbox.search(&["zq user"]), ["zq-app.desktop"]);
+    assert!(sandbox.search(&["zq system"]).is_empty());
+}
+
+#[test]
+fn binaries_duplicating_an_application_are_dropped() {
+    let sandbox = Sandbox::new();
+    sandbox.desktop(
+        "system",
+        "zqcalc.desktop",
+        "Name=ZqCalc\nIcon=calc\nExec=zqcalc\n",
+    );
+    sandbox.binary("zqcalc");
+    sandbox.binary("zqcalc-cli");
+
+    let ids = sandbox.search(&["zqcalc"]);
+    assert_eq!(ids.len(), 2, "{:?}", ids);
+    assert!(ids.contains(&String::from("zqcalc-cli")));
+}
+
+#[test]
+fn run_mode_and_prefix_search_binaries_only() {
+    let sandbox = Sandbox::new();
+    sandbox.desktop("system", "zq-tool.desktop", "Name=Zq Tool\nIcon=tool\n");
+    sandbox.binary("zqtool");
+
+    assert_eq!(sandbox.search(&["--mode", "run", "zqtool"]), ["zqtool"]);
+    assert_eq!(sandbox.search(&[">zqtool"]), ["zqtool"]);
+    assert_eq!(
+        sandbox.search(&["--mode", "apps", "zq tool"]),
+        ["zq-tool.desktop"]
+    );
+}
+
+#[test]
+fn unknown_modes_are_rejected() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run(&["search", "--mode", "bogus", "zq", "--json"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bogus"));
+}